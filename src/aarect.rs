@@ -39,10 +39,12 @@ impl Hittable for XYRect {
         }
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (y - self.y0) / (self.y1 - self.y0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, self.y1 - self.y0, 0.0);
         rec.t = t;
         let outward_normal = Vec3::new(0.0, 0.0, 1.0);
         rec.set_face_normal(r, &outward_normal);
-        rec.mat_ptr = self.mp.clone();
+        rec.set_material(self.mp.clone());
         rec.p = r.at(t);
         true
     }
@@ -90,10 +92,12 @@ impl Hittable for XZRect {
         }
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         rec.t = t;
         let outward_normal = Vec3::new(0.0, 1.0, 0.0);
         rec.set_face_normal(r, &outward_normal);
-        rec.mat_ptr = self.mp.clone();
+        rec.set_material(self.mp.clone());
         rec.p = r.at(t);
         true
     }
@@ -141,10 +145,12 @@ impl Hittable for YZRect {
         }
         rec.u = (y - self.y0) / (self.y1 - self.y0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
+        rec.dpdu = Vec3::new(0.0, self.y1 - self.y0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         rec.t = t;
         let outward_normal = Vec3::new(1.0, 0.0, 0.0);
         rec.set_face_normal(r, &outward_normal);
-        rec.mat_ptr = self.mp.clone();
+        rec.set_material(self.mp.clone());
         rec.p = r.at(t);
         true
     }
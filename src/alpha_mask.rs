@@ -0,0 +1,79 @@
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::texture::*;
+use std::sync::Arc;
+
+// Cuts holes into a primitive where the mask's alpha is below one (leaves, fences from
+// textured quads). Partially transparent hits are kept with probability alpha, so soft edges
+// come out right on average. Rejected hits are skipped and the ray carries on behind them.
+pub struct AlphaMask {
+    ptr: Arc<dyn Hittable>,
+    mask: Arc<dyn Texture>,
+}
+
+impl AlphaMask {
+    pub fn new(p: Arc<dyn Hittable>, mask: Arc<dyn Texture>) -> Self {
+        Self { ptr: p, mask }
+    }
+}
+
+impl Hittable for AlphaMask {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        // rec may hold a closer hit of a sibling, only overwrite it on success
+        let mut temp_rec = rec.clone();
+        let mut t_min = t_min;
+        while self.ptr.hit(r, t_min, t_max, &mut temp_rec) {
+            let alpha = self.mask.alpha(temp_rec.u, temp_rec.v, &temp_rec.p);
+            if alpha >= 1.0 || (alpha > 0.0 && random_double(0.0, 1.0) < alpha) {
+                *rec = temp_rec;
+                return true;
+            }
+            t_min = temp_rec.t + 1e-6;
+        }
+        false
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64, output_box: &mut AABB) -> bool {
+        self.ptr.bounding_box(t0, t1, output_box)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::*;
+    use crate::vec3::*;
+
+    #[test]
+    fn test_hit_behind_transparent_part() {
+        // the near half of the sphere (v < 0.5 seen from below) is cut away
+        struct LowerHalf {}
+        impl Texture for LowerHalf {
+            fn value(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+                Color::ones()
+            }
+            fn alpha(&self, _u: f64, v: f64, _p: &Point) -> f64 {
+                if v < 0.5 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+        let mat = Arc::new(Lambertian::new(Color::ones()));
+        let sphere = Arc::new(Sphere::new(Point::zero(), 1.0, mat.clone()));
+        let masked = AlphaMask::new(sphere, Arc::new(LowerHalf {}));
+
+        let r = Ray {
+            orig: Point::new(0.0, -5.0, 0.0),
+            dire: Vec3::new(0.0, 1.0, 0.0),
+            tm: 0.0,
+        };
+        let mut rec = HitRecord::new(mat);
+        assert!(masked.hit(&r, 0.001, INFINITY, &mut rec));
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::distribution::*;
+use crate::material::luminance;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+
+// What rays that leave the scene see. Backgrounds that light the scene unevenly can be
+// sampled, so that the integrator looks for them directly instead of waiting for paths to
+// escape toward the bright parts.
+pub trait Background: Send + Sync {
+    // radiance arriving from direction `dir` (unit)
+    fn value(&self, dir: &Vec3) -> Color;
+
+    // a direction toward the background and its density in solid angle, None if the
+    // background isn't worth sampling
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    // density of sample() choosing `dir`
+    fn pdf(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+// uniform background of one color
+impl Background for Color {
+    fn value(&self, _dir: &Vec3) -> Color {
+        *self
+    }
+}
+
+// blend from `bottom` straight down to `top` straight up, the sky of the first book
+pub struct SkyGradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl SkyGradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Background for SkyGradient {
+    fn value(&self, dir: &Vec3) -> Color {
+        let t = 0.5 * (dir.y + 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+// (u, v) of a direction in an equirectangular map: -z in the middle, +y at the top
+pub fn direction_to_uv(dir: &Vec3) -> (f64, f64) {
+    let u = 0.5 + dir.x.atan2(-dir.z) / (2.0 * PI);
+    let v = 0.5 + clamp(dir.y, -1.0, 1.0).asin() / PI;
+    (u, v)
+}
+
+pub fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+    let phi = 2.0 * PI * (u - 0.5);
+    let elevation = PI * (v - 0.5);
+    Vec3::new(
+        phi.sin() * elevation.cos(),
+        elevation.sin(),
+        -phi.cos() * elevation.cos(),
+    )
+}
+
+fn rotate_y(dir: &Vec3, degrees: f64) -> Vec3 {
+    let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+    Vec3::new(cos * dir.x + sin * dir.z, dir.y, -sin * dir.x + cos * dir.z)
+}
+
+// Density over directions proportional to a weight tabulated on a width x height
+// equirectangular grid, for backgrounds that can't be sampled analytically
+pub struct DirectionDistribution {
+    distribution: Distribution2D,
+}
+
+impl DirectionDistribution {
+    // `weight(u, v)` is taken at the center of every cell
+    pub fn new<F: Fn(f64, f64) -> f64>(width: usize, height: usize, weight: F) -> Self {
+        let mut func = Vec::with_capacity(height);
+        for row in 0..height {
+            let v = (row as f64 + 0.5) / height as f64;
+            // rows near the poles cover less of the sphere
+            let cos_elevation = (PI * (v - 0.5)).cos();
+            func.push(
+                (0..width)
+                    .map(|column| weight((column as f64 + 0.5) / width as f64, v) * cos_elevation)
+                    .collect(),
+            );
+        }
+        Self {
+            distribution: Distribution2D::new(func),
+        }
+    }
+
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        let ((u, v), pdf) = self
+            .distribution
+            .sample((random_double(0.0, 1.0), random_double(0.0, 1.0)));
+        let cos_elevation = (PI * (v - 0.5)).cos();
+        if pdf <= 0.0 || cos_elevation <= 0.0 {
+            return None;
+        }
+        // d(omega) = cos(elevation) d(phi) d(elevation) = 2 pi^2 cos(elevation) du dv
+        Some((uv_to_direction(u, v), pdf / (2.0 * PI * PI * cos_elevation)))
+    }
+
+    pub fn pdf(&self, dir: &Vec3) -> f64 {
+        let (u, v) = direction_to_uv(dir);
+        let cos_elevation = (PI * (v - 0.5)).cos();
+        if cos_elevation <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * cos_elevation)
+    }
+}
+
+// Image based lighting from an equirectangular (latitude-longitude) image, usually HDR.
+// Directions are sampled in proportion to the luminance of the image.
+pub struct EnvironmentMap {
+    texture: ImageTexture,
+    pub intensity: f64,
+    pub rotation: f64, // degrees around +y
+    distribution: DirectionDistribution,
+}
+
+impl EnvironmentMap {
+    pub fn new(filename: &str, intensity: f64, rotation: f64) -> Self {
+        Self::new_from_texture(ImageTexture::new(filename), intensity, rotation)
+    }
+
+    pub fn new_from_texture(texture: ImageTexture, intensity: f64, rotation: f64) -> Self {
+        let mut texture = texture;
+        texture.wrap = WrapMode::Repeat;
+        texture.filter = FilterMode::Bilinear;
+        let distribution =
+            DirectionDistribution::new(texture.width as usize, texture.height as usize, |u, v| {
+                luminance(&texture.value(u, v, &Point::zero()))
+            });
+        Self {
+            texture,
+            intensity,
+            rotation,
+            distribution,
+        }
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, dir: &Vec3) -> Color {
+        let (u, v) = direction_to_uv(&rotate_y(dir, -self.rotation));
+        self.texture.value(u, v, &Point::zero()) * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        let (dir, pdf) = self.distribution.sample()?;
+        Some((rotate_y(&dir, self.rotation), pdf))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        self.distribution.pdf(&rotate_y(dir, -self.rotation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv_round_trip() {
+        for dir in [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.3, 0.5, 0.2).unit(),
+            Vec3::new(-0.7, -0.1, 0.6).unit(),
+        ]
+        .iter()
+        {
+            let (u, v) = direction_to_uv(dir);
+            assert!((uv_to_direction(u, v) - *dir).length() < 1e-9);
+        }
+        assert_eq!(direction_to_uv(&Vec3::new(0.0, 0.0, -1.0)), (0.5, 0.5));
+    }
+
+    #[test]
+    fn test_sample_pdf() {
+        // dim sky with one bright texel, rotated
+        let mut texels = vec![[0.1, 0.1, 0.1, 1.0]; 16 * 8];
+        texels[2 * 16 + 5] = [50.0, 40.0, 30.0, 1.0];
+        let env = EnvironmentMap::new_from_texture(
+            ImageTexture::new_from_texels(16, 8, texels),
+            2.0,
+            30.0,
+        );
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let (dir, pdf) = env.sample().unwrap();
+            assert!((env.pdf(&dir) - pdf).abs() < 1e-6 * pdf);
+            if env.value(&dir).x > 10.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 500);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::aabb::*;
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+use std::fs;
+use std::sync::Arc;
+
+const TILES_PER_SIDE: usize = 8;
+const NEWTON_ITERATIONS: i32 = 12;
+
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+fn bernstein_deriv(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [
+        -3.0 * s * s,
+        3.0 * s * s - 6.0 * t * s,
+        6.0 * t * s - 3.0 * t * t,
+        3.0 * t * t,
+    ]
+}
+
+// control points of the part of a cubic between a and b (de Casteljau)
+pub fn sub_curve(c: &[Point; 4], a: f64, b: f64) -> [Point; 4] {
+    let split = |c: &[Point; 4], t: f64| -> ([Point; 4], [Point; 4]) {
+        let p01 = c[0] + (c[1] - c[0]) * t;
+        let p12 = c[1] + (c[2] - c[1]) * t;
+        let p23 = c[2] + (c[3] - c[2]) * t;
+        let p012 = p01 + (p12 - p01) * t;
+        let p123 = p12 + (p23 - p12) * t;
+        let mid = p012 + (p123 - p012) * t;
+        ([c[0], p01, p012, mid], [mid, p123, p23, c[3]])
+    };
+    let (_, right) = split(c, a);
+    if a >= 1.0 {
+        return right;
+    }
+    let (left, _) = split(&right, (b - a) / (1.0 - a));
+    left
+}
+
+pub struct BicubicPatch {
+    pub control: [[Point; 4]; 4], // control[i][j], i along u and j along v
+}
+
+impl BicubicPatch {
+    pub fn eval(&self, u: f64, v: f64) -> (Point, Vec3, Vec3) {
+        let bu = bernstein(u);
+        let bv = bernstein(v);
+        let du = bernstein_deriv(u);
+        let dv = bernstein_deriv(v);
+        let mut p = Point::zero();
+        let mut dpdu = Vec3::zero();
+        let mut dpdv = Vec3::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                let c = self.control[i][j];
+                p += c * (bu[i] * bv[j]);
+                dpdu += c * (du[i] * bv[j]);
+                dpdv += c * (bu[i] * dv[j]);
+            }
+        }
+        (p, dpdu, dpdv)
+    }
+
+    pub fn normal(&self, u: f64, v: f64) -> Vec3 {
+        let (_, dpdu, dpdv) = self.eval(u, v);
+        let n = Vec3::cross(dpdu, dpdv);
+        if n.squared_length() > 1e-20 {
+            return n.unit();
+        }
+        // degenerate edge (e.g. the pole of the teapot lid), step into the patch
+        let eps = 1e-4;
+        let u = if u > 0.5 { u - eps } else { u + eps };
+        let v = if v > 0.5 { v - eps } else { v + eps };
+        let (_, dpdu, dpdv) = self.eval(u, v);
+        let n = Vec3::cross(dpdu, dpdv);
+        if n.squared_length() > 0.0 {
+            n.unit()
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        }
+    }
+
+    fn tile_box(&self, u0: f64, u1: f64, v0: f64, v1: f64) -> AABB {
+        let mut columns = [[Point::zero(); 4]; 4];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..4 {
+            let row = sub_curve(&self.control[i], v0, v1);
+            for j in 0..4 {
+                columns[j][i] = row[j];
+            }
+        }
+        let mut min = Point::new(INFINITY, INFINITY, INFINITY);
+        let mut max = -min;
+        for column in columns.iter() {
+            for c in sub_curve(column, u0, u1).iter() {
+                for a in 0..3 {
+                    min[a] = min[a].min(c[a]);
+                    max[a] = max[a].max(c[a]);
+                }
+            }
+        }
+        let pad = ((max - min).length() * 1e-3).max(1e-6);
+        AABB::new(&(min - pad), &(max + pad))
+    }
+}
+
+// A parameter-space tile of a patch. Its box comes from the tile's own control points, and
+// hits are found by Newton iteration on the exact surface starting from the tile center.
+struct PatchTile {
+    patch: Arc<BicubicPatch>,
+    u0: f64,
+    u1: f64,
+    v0: f64,
+    v1: f64,
+    bbox: AABB,
+    mp: Arc<dyn Material>,
+}
+
+impl Hittable for PatchTile {
+    #[allow(clippy::many_single_char_names)]
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return false;
+        }
+        let mut u = (self.u0 + self.u1) / 2.0;
+        let mut v = (self.v0 + self.v1) / 2.0;
+        let (p, _, _) = self.patch.eval(u, v);
+        let mut t = ((p - r.orig) * r.dire) / r.dire.squared_length();
+        let tolerance = 1e-9
+            * (self.bbox._max - self.bbox._min)
+                .squared_length()
+                .max(1e-12);
+        let margin_u = (self.u1 - self.u0) * 0.1;
+        let margin_v = (self.v1 - self.v0) * 0.1;
+
+        let mut converged = false;
+        for _i in 0..NEWTON_ITERATIONS {
+            let (p, dpdu, dpdv) = self.patch.eval(u, v);
+            let f = p - r.at(t);
+            if f.squared_length() < tolerance {
+                converged = true;
+                break;
+            }
+            // solve [dpdu dpdv -dire] * (du, dv, dt) = -f with Cramer's rule
+            let c = -r.dire;
+            let det = dpdu * Vec3::cross(dpdv, c);
+            if det.abs() < 1e-14 {
+                return false;
+            }
+            let du = -f * Vec3::cross(dpdv, c) / det;
+            let dv = dpdu * Vec3::cross(-f, c) / det;
+            let dt = dpdu * Vec3::cross(dpdv, -f) / det;
+            u = (u + du).max(self.u0 - margin_u).min(self.u1 + margin_u);
+            v = (v + dv).max(self.v0 - margin_v).min(self.v1 + margin_v);
+            t += dt;
+        }
+        if !converged {
+            let (p, _, _) = self.patch.eval(u, v);
+            converged = (p - r.at(t)).squared_length() < tolerance;
+        }
+        if !converged
+            || t < t_min
+            || t > t_max
+            || u < self.u0 - margin_u * 0.5
+            || u > self.u1 + margin_u * 0.5
+            || v < self.v0 - margin_v * 0.5
+            || v > self.v1 + margin_v * 0.5
+            || !(0.0..=1.0).contains(&u)
+            || !(0.0..=1.0).contains(&v)
+        {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        let outward_normal = self.patch.normal(u, v);
+        rec.set_face_normal(r, &outward_normal);
+        let (_, dpdu, dpdv) = self.patch.eval(u, v);
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        rec.u = u;
+        rec.v = v;
+        rec.set_material(self.mp.clone());
+        true
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.bbox.clone();
+        true
+    }
+}
+
+pub struct BezierPatch {
+    tiles: BVHNode,
+    bbox: AABB,
+}
+
+impl BezierPatch {
+    pub fn new(control: [[Point; 4]; 4], mp: Arc<dyn Material>) -> Self {
+        let patch = Arc::new(BicubicPatch { control });
+        let mut tiles = HittableList::new();
+        let step = 1.0 / TILES_PER_SIDE as f64;
+        for i in 0..TILES_PER_SIDE {
+            for j in 0..TILES_PER_SIDE {
+                let (u0, u1) = (i as f64 * step, (i + 1) as f64 * step);
+                let (v0, v1) = (j as f64 * step, (j + 1) as f64 * step);
+                tiles.add(Arc::new(PatchTile {
+                    patch: patch.clone(),
+                    u0,
+                    u1,
+                    v0,
+                    v1,
+                    bbox: patch.tile_box(u0, u1, v0, v1),
+                    mp: mp.clone(),
+                }));
+            }
+        }
+        let mut bbox = AABB::new(&Point::ones(), &Point::ones());
+        tiles.bounding_box(0.0, 1.0, &mut bbox);
+        Self {
+            tiles: BVHNode::new(&mut tiles, 0.0, 1.0),
+            bbox,
+        }
+    }
+}
+
+impl Hittable for BezierPatch {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.tiles.hit(r, t_min, t_max, rec)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.bbox.clone();
+        true
+    }
+}
+
+// Reads the classic Utah teapot patch format: the patch count, one line of 16 one-based
+// control point indices per patch, the vertex count, then one "x, y, z" line per vertex.
+pub fn load_bezier_patches(filename: &str, mp: Arc<dyn Material>) -> HittableList {
+    let contents = fs::read_to_string(filename).expect("failed to read the patch file");
+    parse_bezier_patches(&contents, mp)
+}
+
+pub fn parse_bezier_patches(text: &str, mp: Arc<dyn Material>) -> HittableList {
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    let numbers = |line: &str| -> Vec<f64> {
+        line.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f64>().expect("bad number in patch file"))
+            .collect()
+    };
+    let count = |line: &str| -> usize {
+        let n = numbers(line);
+        assert_eq!(
+            n.len(),
+            1,
+            "expected a count in patch file, got \"{}\"",
+            line
+        );
+        n[0] as usize
+    };
+
+    let patch_count = count(lines.next().expect("missing patch count"));
+    let mut indices = Vec::with_capacity(patch_count);
+    for _i in 0..patch_count {
+        let idx = numbers(lines.next().expect("missing patch"));
+        assert_eq!(idx.len(), 16, "a patch needs 16 control points");
+        indices.push(idx);
+    }
+
+    let vertex_count = count(lines.next().expect("missing vertex count"));
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for _i in 0..vertex_count {
+        let xyz = numbers(lines.next().expect("missing vertex"));
+        assert_eq!(xyz.len(), 3, "a vertex needs x, y and z");
+        vertices.push(Point::new(xyz[0], xyz[1], xyz[2]));
+    }
+
+    let mut list = HittableList::new();
+    for idx in indices.iter() {
+        let mut control = [[Point::zero(); 4]; 4];
+        for (k, i) in idx.iter().enumerate() {
+            assert!(
+                *i >= 1.0 && *i <= vertices.len() as f64 && i.fract() == 0.0,
+                "control point index {} out of range, vertices are numbered 1 to {}",
+                i,
+                vertices.len()
+            );
+            control[k / 4][k % 4] = vertices[*i as usize - 1];
+        }
+        list.add(Arc::new(BezierPatch::new(control, mp.clone())));
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the unit square in the y = 0 plane, u along x and v along z
+    fn flat_patch_text(first_index: i32) -> String {
+        let mut text = String::from("1\n");
+        let indices: Vec<String> = (0..16).map(|k| (k + first_index).to_string()).collect();
+        text += &indices.join(", ");
+        text += "\n16\n";
+        for i in 0..4 {
+            for j in 0..4 {
+                text += &format!("{}, 0.0, {}\n", i as f64 / 3.0, j as f64 / 3.0);
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_hit_flat_patch() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::ones()));
+        let patches = parse_bezier_patches(&flat_patch_text(1), mat.clone());
+        let r = Ray {
+            orig: Point::new(0.25, 2.0, 0.6),
+            dire: Vec3::new(0.0, -1.0, 0.0),
+            tm: 0.0,
+        };
+        let mut rec = HitRecord::new(mat);
+        assert!(patches.hit(&r, 0.001, INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-6, "{}", rec.t);
+        assert!((rec.u - 0.25).abs() < 1e-6 && (rec.v - 0.6).abs() < 1e-6);
+        assert!((rec.normal.y.abs() - 1.0).abs() < 1e-9);
+
+        let miss = Ray {
+            orig: Point::new(1.25, 2.0, 0.6),
+            ..r
+        };
+        assert!(!patches.hit(&miss, 0.001, INFINITY, &mut rec));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_reject_zero_index() {
+        let mat = Arc::new(Lambertian::new(Color::ones()));
+        parse_bezier_patches(&flat_patch_text(0), mat);
+    }
+}
@@ -14,61 +14,47 @@ pub struct Box6 {
 
 impl Box6 {
     pub fn new(p0: &Point, p1: &Point, ptr: Arc<dyn Material>) -> Self {
+        Self::new_with_sides(
+            p0,
+            p1,
+            [
+                ptr.clone(),
+                ptr.clone(),
+                ptr.clone(),
+                ptr.clone(),
+                ptr.clone(),
+                ptr,
+            ],
+        )
+    }
+
+    // one material per side, ordered +z, -z, +y, -y, +x, -x
+    pub fn new_with_sides(p0: &Point, p1: &Point, mats: [Arc<dyn Material>; 6]) -> Self {
         let mut tmp = Self {
             box_min: *p0,
             box_max: *p1,
             sides: HittableList::new(),
         };
-        tmp.sides.add(Arc::new(XYRect::new(
-            p0.x,
-            p1.x,
-            p0.y,
-            p1.y,
-            p1.z,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(XYRect::new(
-            p0.x,
-            p1.x,
-            p0.y,
-            p1.y,
-            p0.z,
-            ptr.clone(),
-        )));
+        let [front, back, top, bottom, right, left] = mats;
+
+        // the sides at p0 are flipped so that front faces are on the outside
+        tmp.sides
+            .add(Arc::new(XYRect::new(p0.x, p1.x, p0.y, p1.y, p1.z, front)));
+        tmp.sides.add(Arc::new(FlipFace::new(Arc::new(XYRect::new(
+            p0.x, p1.x, p0.y, p1.y, p0.z, back,
+        )))));
 
-        tmp.sides.add(Arc::new(XZRect::new(
-            p0.x,
-            p1.x,
-            p0.z,
-            p1.z,
-            p1.y,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(XZRect::new(
-            p0.x,
-            p1.x,
-            p0.z,
-            p1.z,
-            p0.y,
-            ptr.clone(),
-        )));
+        tmp.sides
+            .add(Arc::new(XZRect::new(p0.x, p1.x, p0.z, p1.z, p1.y, top)));
+        tmp.sides.add(Arc::new(FlipFace::new(Arc::new(XZRect::new(
+            p0.x, p1.x, p0.z, p1.z, p0.y, bottom,
+        )))));
 
-        tmp.sides.add(Arc::new(YZRect::new(
-            p0.y,
-            p1.y,
-            p0.z,
-            p1.z,
-            p1.x,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(YZRect::new(
-            p0.y,
-            p1.y,
-            p0.z,
-            p1.z,
-            p0.x,
-            ptr.clone(),
-        )));
+        tmp.sides
+            .add(Arc::new(YZRect::new(p0.y, p1.y, p0.z, p1.z, p1.x, right)));
+        tmp.sides.add(Arc::new(FlipFace::new(Arc::new(YZRect::new(
+            p0.y, p1.y, p0.z, p1.z, p0.x, left,
+        )))));
 
         tmp
     }
@@ -1,4 +1,4 @@
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::rtweekend::{degrees_to_radians, random_double};
 use crate::vec3::{random_in_unit_disk, Point, Vec3};
 
@@ -58,15 +58,25 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+    // the ray for (s, t) along with the rays for (s + ds, t) and (s, t + dt) through the same
+    // point on the lens at the same time, ds and dt being the size of a pixel
+    pub fn get_ray(&self, s: f64, t: f64, ds: f64, dt: f64) -> (Ray, RayDifferential) {
         let rd = random_in_unit_disk() * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray {
+        let tm = random_double(self.time0, self.time1);
+        let through = |s: f64, t: f64| Ray {
             orig: self.orig + offset,
             dire: self.lower_left_corner + self.horizontal * s + self.vertical * t
                 - self.orig
                 - offset,
-            tm: random_double(self.time0, self.time1),
-        }
+            tm,
+        };
+        (
+            through(s, t),
+            RayDifferential {
+                rx: through(s + ds, t),
+                ry: through(s, t + dt),
+            },
+        )
     }
 }
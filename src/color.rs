@@ -1,7 +1,11 @@
+use crate::background::Background;
 use crate::hittable;
+use crate::light::{material_id, LightList};
 use crate::material::Lambertian;
-use crate::ray::Ray;
+use crate::medium::MediumStack;
+use crate::ray::{Ray, RayDifferential};
 pub use crate::rtweekend::{clamp, INFINITY, PI};
+use crate::spectrum::{rgb_to_spectrum, sample_wavelength, spectral_to_rgb};
 use crate::vec3::{Color, Vec3};
 pub use hittable::{HitRecord, Hittable, HittableList, Sphere};
 use image::RgbImage;
@@ -31,33 +35,242 @@ pub fn write_color(
     ]);
 }
 
-pub fn ray_color(r: &Ray, background: &Color, world: &dyn Hittable, depth: i32) -> Color {
+// what paths can meet: the surfaces and media, light sources and what's beyond them
+pub struct Scene<'a> {
+    pub world: &'a dyn Hittable,
+    pub background: &'a dyn Background,
+    pub lights: &'a LightList,
+}
+
+pub fn ray_color(r: &Ray, diff: &RayDifferential, scene: &Scene, depth: i32) -> Color {
+    let mut path = Path {
+        media: MediumStack::new(),
+        wavelength: None,
+        differential: Some(diff.clone()),
+        bsdf_pdf: None,
+    };
+    trace(r, scene, depth, &mut path)
+}
+
+// Traces a single wavelength, returns the estimate of the pixel color in linear sRGB
+pub fn ray_color_spectral(r: &Ray, diff: &RayDifferential, scene: &Scene, depth: i32) -> Color {
+    let wavelength = sample_wavelength();
+    let mut path = Path {
+        media: MediumStack::new(),
+        wavelength: Some(wavelength),
+        differential: Some(diff.clone()),
+        bsdf_pdf: None,
+    };
+    let value = trace(r, scene, depth, &mut path);
+    spectral_to_rgb(value.y, wavelength)
+}
+
+// State along a path: the interfaces it is inside of, changing as it crosses surfaces, and
+// in spectral mode its wavelength, the differentials of the camera ray until it hits, and
+// the density with which the last surface chose the ray, for weighting what it finds
+struct Path {
+    media: MediumStack,
+    wavelength: Option<f64>,
+    differential: Option<RayDifferential>,
+    bsdf_pdf: Option<f64>,
+}
+
+impl Path {
+    // colors become the spectral value at the path's wavelength in all three channels
+    fn color(&self, c: Color) -> Color {
+        match self.wavelength {
+            Some(wavelength) => Color::ones() * rgb_to_spectrum(&c, wavelength),
+            None => c,
+        }
+    }
+}
+
+fn trace(r: &Ray, scene: &Scene, depth: i32, path: &mut Path) -> Color {
     let mut rec = HitRecord::new(Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0))));
+    rec.wavelength = path.wavelength;
+    rec.time = r.tm;
 
     if depth <= 0 {
         return Color::new(0.0, 0.0, 0.0);
     }
 
-    if !world.hit(r, 0.0001, INFINITY, &mut rec) {
-        return *background;
+    let hit = scene.world.hit(r, 0.0001, INFINITY, &mut rec);
+    if let Some(diff) = path.differential.take() {
+        if hit {
+            rec.compute_differentials(&diff);
+        }
+    }
+    if let Some(medium) = path.media.current_medium() {
+        let (scatter_t, weight) = medium.sample(r, if hit { rec.t } else { INFINITY });
+        let weight = path.color(weight);
+        if let Some(t) = scatter_t {
+            let scattered = Ray {
+                orig: r.at(t),
+                dire: medium.phase_function().sample(&r.dire.unit()),
+                tm: r.tm,
+            };
+            return Vec3::elemul(weight, trace(&scattered, scene, depth - 1, path));
+        }
+        return Vec3::elemul(weight, shade(r, hit, &mut rec, scene, depth, path));
+    }
+    shade(r, hit, &mut rec, scene, depth, path)
+}
+
+fn shade(
+    r: &Ray,
+    hit: bool,
+    rec: &mut HitRecord,
+    scene: &Scene,
+    depth: i32,
+    path: &mut Path,
+) -> Color {
+    // only set when this ray left a surface that also sampled the lights directly
+    let bsdf_pdf = path.bsdf_pdf.take();
+    if !hit {
+        let value = path.color(scene.background.value(&r.dire.unit()));
+        return match bsdf_pdf {
+            Some(pdf_b) => value * power_heuristic(pdf_b, scene.background.pdf(&r.dire.unit())),
+            None => value,
+        };
+    }
+    let tint = path.color(rec.tint);
+    if let Some(phase) = &rec.phase {
+        let scattered = Ray {
+            orig: rec.p,
+            dire: phase.sample(&r.dire.unit()),
+            tm: r.tm,
+        };
+        return Vec3::elemul(tint, trace(&scattered, scene, depth - 1, path));
     }
+
+    let id = material_id(&rec.mat_ptr);
+    let interface = rec.mat_ptr.interface(rec);
+    if let Some(interface) = &interface {
+        match path.media.outer_ior(id, interface, rec.front_face) {
+            Some(ior) => rec.outer_ior = ior,
+            None => {
+                // hidden inside a higher priority volume, go straight on
+                path.media.cross(id, interface, rec.front_face);
+                let continued = Ray {
+                    orig: rec.p,
+                    dire: r.dire,
+                    tm: r.tm,
+                };
+                return trace(&continued, scene, depth - 1, path);
+            }
+        }
+    }
+
     let mut scattered = Ray {
         orig: Vec3::ones(),
         dire: Vec3::ones(),
         tm: 0.0,
     };
     let mut attenuation = Color::new(0.0, 0.0, 0.0);
-    let emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
+    let mut emitted = Vec3::elemul(tint, path.color(rec.mat_ptr.emitted(r, rec)));
+    if let Some(pdf_b) = bsdf_pdf {
+        if emitted != Color::zero() {
+            let pdf_l = scene.lights.pdf_hit(id, &r.orig, &r.dire.unit());
+            emitted *= power_heuristic(pdf_b, pdf_l);
+        }
+    }
 
     if !rec
         .mat_ptr
-        .scatter(&r, &rec, &mut attenuation, &mut scattered)
+        .scatter(&r, rec, &mut attenuation, &mut scattered)
     {
         return emitted;
     }
+    if let Some(interface) = &interface {
+        if scattered.dire * rec.normal < 0.0 {
+            path.media.cross(id, interface, rec.front_face);
+        }
+    }
+
+    // light from the lights and the background, sampled directly and combined with the
+    // scattered ray by multiple importance sampling, for surfaces that can be evaluated in
+    // any direction
+    let mut direct = Color::zero();
+    let pdf_b = rec.mat_ptr.scattering_pdf(r, rec, &scattered);
+    if pdf_b > 0.0 && depth > 1 && path.media.current_medium().is_none() {
+        direct = Vec3::elemul(
+            tint,
+            sample_lights(r, rec, scene, path) + sample_background(r, rec, scene, path),
+        );
+        path.bsdf_pdf = Some(pdf_b);
+    }
     emitted
+        + direct
         + Vec3::elemul(
-            attenuation,
-            ray_color(&scattered, background, world, depth - 1),
+            Vec3::elemul(tint, path.color(attenuation)),
+            trace(&scattered, scene, depth - 1, path),
         )
 }
+
+// whether anything is in the way along `shadow` before `t_max`
+fn occluded(shadow: &Ray, t_max: f64, rec: &HitRecord, scene: &Scene, path: &Path) -> bool {
+    let mut blocker = HitRecord::new(rec.mat_ptr.clone());
+    blocker.wavelength = path.wavelength;
+    blocker.time = shadow.tm;
+    scene.world.hit(shadow, 0.0001, t_max, &mut blocker)
+}
+
+// one shadow ray toward a sample of one of the lights
+fn sample_lights(r: &Ray, rec: &HitRecord, scene: &Scene, path: &Path) -> Color {
+    let (light, probability) = match scene.lights.pick(&rec.p) {
+        Some(pick) => pick,
+        None => return Color::zero(),
+    };
+    let sample = match light.sample(&rec.p) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return Color::zero(),
+    };
+    let shadow = Ray {
+        orig: rec.p,
+        dire: sample.dir,
+        tm: r.tm,
+    };
+    let f = rec.mat_ptr.eval(r, rec, &shadow);
+    if f == Color::zero() || occluded(&shadow, sample.distance * (1.0 - 1e-4), rec, scene, path) {
+        return Color::zero();
+    }
+    let pdf_l = probability * sample.pdf;
+    // rays never hit delta lights, so they take all of the weight
+    let weight = if sample.delta {
+        1.0 / pdf_l
+    } else {
+        power_heuristic(pdf_l, rec.mat_ptr.scattering_pdf(r, rec, &shadow)) / pdf_l
+    };
+    Vec3::elemul(path.color(f), path.color(sample.radiance)) * weight
+}
+
+// one shadow ray toward a sample of the background, weighted against the surface sampling
+// the same direction
+fn sample_background(r: &Ray, rec: &HitRecord, scene: &Scene, path: &Path) -> Color {
+    let (dir, pdf_l) = match scene.background.sample() {
+        Some(sample) => sample,
+        None => return Color::zero(),
+    };
+    let shadow = Ray {
+        orig: rec.p,
+        dire: dir,
+        tm: r.tm,
+    };
+    let f = rec.mat_ptr.eval(r, rec, &shadow);
+    if f == Color::zero() || occluded(&shadow, INFINITY, rec, scene, path) {
+        return Color::zero();
+    }
+    let pdf_b = rec.mat_ptr.scattering_pdf(r, rec, &shadow);
+    Vec3::elemul(path.color(f), path.color(scene.background.value(&dir)))
+        * (power_heuristic(pdf_l, pdf_b) / pdf_l)
+}
+
+// weight of a sample from the strategy with density `pdf_f` against one with `pdf_g`
+fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+    let (f2, g2) = (pdf_f * pdf_f, pdf_g * pdf_g);
+    if f2 + g2 > 0.0 {
+        f2 / (f2 + g2)
+    } else {
+        0.0
+    }
+}
@@ -1,6 +1,7 @@
 use crate::aabb::*;
 use crate::hittable::*;
 use crate::material::*;
+use crate::phase::*;
 use crate::ray::*;
 use crate::rtweekend::*;
 use crate::texture::*;
@@ -9,23 +10,29 @@ use std::sync::Arc;
 
 pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
-    phase_function: Arc<dyn Material>,
+    phase_function: Arc<dyn PhaseFunction>,
+    albedo: Arc<dyn Texture>,
     neg_inv_density: f64,
 }
 
 impl ConstantMedium {
     pub fn new(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
-        Self {
-            boundary: b,
-            neg_inv_density: -1.0 / d,
-            phase_function: Arc::new(Isotropic::new(a)),
-        }
+        Self::new_with_phase(b, d, a, Arc::new(Isotropic::new()))
     }
     pub fn new_from_color(b: Arc<dyn Hittable>, d: f64, c: Color) -> Self {
+        Self::new(b, d, Arc::new(SolidColor::new(c)))
+    }
+    pub fn new_with_phase(
+        b: Arc<dyn Hittable>,
+        d: f64,
+        a: Arc<dyn Texture>,
+        phase: Arc<dyn PhaseFunction>,
+    ) -> Self {
         Self {
             boundary: b,
             neg_inv_density: -1.0 / d,
-            phase_function: Arc::new(Isotropic::new_from_color(c)),
+            phase_function: phase,
+            albedo: a,
         }
     }
 }
@@ -82,9 +89,8 @@ impl Hittable for ConstantMedium {
             );
         }
 
-        rec.normal = Vec3::new(1.0, 0.0, 0.0); // arbitrary
-        rec.front_face = true; // arbitrary
-        rec.mat_ptr = self.phase_function.clone();
+        let albedo = self.albedo.value_at(rec);
+        rec.set_phase(self.phase_function.clone(), albedo);
         true
     }
 
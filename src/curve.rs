@@ -0,0 +1,409 @@
+use crate::aabb::*;
+use crate::bezier::sub_curve;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+#[derive(Clone, Copy)]
+pub enum CurveType {
+    Ribbon(Vec3, Vec3), // flat strip oriented by the normals at both ends (grass blades)
+    Tube,               // faces the ray but shades like a round cylinder (hair, fur)
+}
+
+struct CurveCommon {
+    cp: [Point; 4],
+    width: [f64; 2],
+    kind: CurveType,
+    normal_angle: f64,
+    inv_sin_normal_angle: f64,
+    mp: Arc<dyn Material>,
+}
+
+// One segment [u_min, u_max] of a cubic Bezier curve with linearly varying width.
+pub struct Curve {
+    common: Arc<CurveCommon>,
+    u_min: f64,
+    u_max: f64,
+}
+
+fn eval_bezier(cp: &[Point; 4], u: f64) -> (Point, Vec3) {
+    let cp1 = [
+        cp[0] + (cp[1] - cp[0]) * u,
+        cp[1] + (cp[2] - cp[1]) * u,
+        cp[2] + (cp[3] - cp[2]) * u,
+    ];
+    let cp2 = [
+        cp1[0] + (cp1[1] - cp1[0]) * u,
+        cp1[1] + (cp1[2] - cp1[1]) * u,
+    ];
+    let deriv = if (cp2[1] - cp2[0]).squared_length() > 0.0 {
+        (cp2[1] - cp2[0]) * 3.0
+    } else {
+        // degenerate first or last control point
+        cp[3] - cp[0]
+    };
+    (cp2[0] + (cp2[1] - cp2[0]) * u, deriv)
+}
+
+fn split_half(cp: &[Point; 4]) -> [Point; 7] {
+    [
+        cp[0],
+        (cp[0] + cp[1]) / 2.0,
+        (cp[0] + cp[1] * 2.0 + cp[2]) / 4.0,
+        (cp[0] + cp[1] * 3.0 + cp[2] * 3.0 + cp[3]) / 8.0,
+        (cp[1] + cp[2] * 2.0 + cp[3]) / 4.0,
+        (cp[2] + cp[3]) / 2.0,
+        cp[3],
+    ]
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    (1.0 - t) * a + t * b
+}
+
+impl Curve {
+    pub fn new(
+        cp: [Point; 4],
+        width0: f64,
+        width1: f64,
+        kind: CurveType,
+        mp: Arc<dyn Material>,
+    ) -> Self {
+        let (normal_angle, inv_sin_normal_angle) = match kind {
+            CurveType::Ribbon(n0, n1) => {
+                let angle = clamp(n0.unit() * n1.unit(), 0.0, 1.0).acos();
+                (angle, 1.0 / angle.sin())
+            }
+            CurveType::Tube => (0.0, 0.0),
+        };
+        Self {
+            common: Arc::new(CurveCommon {
+                cp,
+                width: [width0, width1],
+                kind,
+                normal_angle,
+                inv_sin_normal_angle,
+                mp,
+            }),
+            u_min: 0.0,
+            u_max: 1.0,
+        }
+    }
+
+    // Splits the curve into segments so the BVH gets tight per-segment bounds.
+    pub fn new_segments(
+        cp: [Point; 4],
+        width0: f64,
+        width1: f64,
+        kind: CurveType,
+        segments: usize,
+        mp: Arc<dyn Material>,
+    ) -> HittableList {
+        let whole = Self::new(cp, width0, width1, kind, mp);
+        let mut list = HittableList::new();
+        for i in 0..segments {
+            list.add(Arc::new(Self {
+                common: whole.common.clone(),
+                u_min: i as f64 / segments as f64,
+                u_max: (i + 1) as f64 / segments as f64,
+            }));
+        }
+        list
+    }
+
+    fn ribbon_normal(&self, u: f64) -> Vec3 {
+        match self.common.kind {
+            CurveType::Ribbon(n0, n1) => {
+                let (n0, n1) = (n0.unit(), n1.unit());
+                if self.common.normal_angle == 0.0 {
+                    return n0;
+                }
+                let sin0 =
+                    ((1.0 - u) * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
+                let sin1 = (u * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
+                n0 * sin0 + n1 * sin1
+            }
+            CurveType::Tube => Vec3::zero(),
+        }
+    }
+
+    // intersection in a space where the ray starts at the origin and runs along +z
+    #[allow(clippy::too_many_arguments)]
+    fn recursive_intersect(
+        &self,
+        r: &Ray,
+        basis: &[Vec3; 3],
+        z_min: f64,
+        z_max: f64,
+        cp: &[Point; 4],
+        u0: f64,
+        u1: f64,
+        depth: i32,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let ray_length = r.dire.length();
+
+        if depth > 0 {
+            let split = split_half(cp);
+            let u = [u0, (u0 + u1) / 2.0, u1];
+            let mut hit = false;
+            let mut z_max = z_max;
+            for seg in 0..2 {
+                let c = [
+                    split[seg * 3],
+                    split[seg * 3 + 1],
+                    split[seg * 3 + 2],
+                    split[seg * 3 + 3],
+                ];
+                let max_width = lerp(u[seg], self.common.width[0], self.common.width[1]).max(lerp(
+                    u[seg + 1],
+                    self.common.width[0],
+                    self.common.width[1],
+                ));
+                let half = max_width * 0.5;
+                let mut min = Point::new(INFINITY, INFINITY, INFINITY);
+                let mut max = -min;
+                for p in c.iter() {
+                    for a in 0..3 {
+                        min[a] = min[a].min(p[a]);
+                        max[a] = max[a].max(p[a]);
+                    }
+                }
+                if max.x + half < 0.0
+                    || min.x - half > 0.0
+                    || max.y + half < 0.0
+                    || min.y - half > 0.0
+                    || max.z + half < z_min
+                    || min.z - half > z_max
+                {
+                    continue;
+                }
+                if self.recursive_intersect(
+                    r,
+                    basis,
+                    z_min,
+                    z_max,
+                    &c,
+                    u[seg],
+                    u[seg + 1],
+                    depth - 1,
+                    rec,
+                ) {
+                    hit = true;
+                    z_max = rec.t * ray_length;
+                }
+            }
+            return hit;
+        }
+
+        // test the sample point against the tangent perpendicular at the curve start and end
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return false;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return false;
+        }
+
+        // closest point on the segment's chord to the ray
+        let seg_x = cp[3].x - cp[0].x;
+        let seg_y = cp[3].y - cp[0].y;
+        let denom = seg_x * seg_x + seg_y * seg_y;
+        if denom == 0.0 {
+            return false;
+        }
+        let w = (-cp[0].x * seg_x - cp[0].y * seg_y) / denom;
+
+        let u = clamp(lerp(w, u0, u1), u0, u1);
+        let mut hit_width = lerp(u, self.common.width[0], self.common.width[1]);
+        let mut n_hit = Vec3::zero();
+        if let CurveType::Ribbon(_, _) = self.common.kind {
+            n_hit = self.ribbon_normal(u);
+            hit_width *= (n_hit * r.dire).abs() / ray_length;
+        }
+
+        let (pc, dpcdw) = eval_bezier(cp, clamp(w, 0.0, 1.0));
+        let pt_curve_dist2 = pc.x * pc.x + pc.y * pc.y;
+        if pt_curve_dist2 > hit_width * hit_width * 0.25 {
+            return false;
+        }
+        if pc.z < z_min || pc.z > z_max {
+            return false;
+        }
+
+        let pt_curve_dist = pt_curve_dist2.sqrt();
+        let edge_func = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if edge_func > 0.0 {
+            0.5 + pt_curve_dist / hit_width
+        } else {
+            0.5 - pt_curve_dist / hit_width
+        };
+
+        let to_world = |a: &Vec3| basis[0] * a.x + basis[1] * a.y + basis[2] * a.z;
+        let (_, dpdu) = eval_bezier(&self.common.cp, u);
+        let dpdv = match self.common.kind {
+            CurveType::Ribbon(_, _) => Vec3::cross(n_hit, dpdu).unit() * hit_width,
+            CurveType::Tube => {
+                let dpdu_plane = Vec3::new(dpdu * basis[0], dpdu * basis[1], dpdu * basis[2]);
+                let dpdv_plane = Vec3::new(-dpdu_plane.y, dpdu_plane.x, 0.0).unit() * hit_width;
+                // rotate around the tangent so the shading normal wraps around like a cylinder
+                let theta = -lerp(v, -PI / 2.0, PI / 2.0);
+                let axis = dpdu_plane.unit();
+                let rotated = dpdv_plane * theta.cos()
+                    + Vec3::cross(axis, dpdv_plane) * theta.sin()
+                    + axis * (axis * dpdv_plane) * (1.0 - theta.cos());
+                to_world(&rotated)
+            }
+        };
+
+        rec.t = pc.z / ray_length;
+        rec.p = r.at(rec.t);
+        rec.u = u;
+        rec.v = v;
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        let outward_normal = Vec3::cross(dpdu, dpdv).unit();
+        rec.set_face_normal(r, &outward_normal);
+        rec.set_material(self.common.mp.clone());
+        true
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let cp = sub_curve(&self.common.cp, self.u_min, self.u_max);
+
+        // orthonormal basis with the ray direction as z, x roughly along the segment
+        let dz = r.dire.unit();
+        let chord = cp[3] - cp[0];
+        let mut dx = chord - dz * (chord * dz);
+        if dx.squared_length() < 1e-12 {
+            dx = if dz.x.abs() > 0.9 {
+                Vec3::cross(Vec3::new(0.0, 1.0, 0.0), dz)
+            } else {
+                Vec3::cross(Vec3::new(1.0, 0.0, 0.0), dz)
+            };
+        }
+        let dx = dx.unit();
+        let dy = Vec3::cross(dz, dx);
+        let basis = [dx, dy, dz];
+        let to_ray = |p: &Point| {
+            let d = *p - r.orig;
+            Point::new(d * dx, d * dy, d * dz)
+        };
+        let cp_ray = [
+            to_ray(&cp[0]),
+            to_ray(&cp[1]),
+            to_ray(&cp[2]),
+            to_ray(&cp[3]),
+        ];
+
+        // refinement depth from the curvature, so the final pieces are nearly straight
+        let mut l0: f64 = 0.0;
+        for w in cp_ray.windows(3) {
+            let d = w[0] - w[1] * 2.0 + w[2];
+            l0 = l0.max(d.x.abs()).max(d.y.abs()).max(d.z.abs());
+        }
+        let eps = self.common.width[0].max(self.common.width[1]) * 0.05;
+        let max_depth = if l0 > 0.0 && eps > 0.0 {
+            let r0 = (std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() / 2.0;
+            clamp(r0, 0.0, 10.0) as i32
+        } else {
+            0
+        };
+
+        let ray_length = r.dire.length();
+        self.recursive_intersect(
+            r,
+            &basis,
+            t_min * ray_length,
+            t_max * ray_length,
+            &cp_ray,
+            self.u_min,
+            self.u_max,
+            max_depth,
+            rec,
+        )
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
+        let cp = sub_curve(&self.common.cp, self.u_min, self.u_max);
+        let mut min = Point::new(INFINITY, INFINITY, INFINITY);
+        let mut max = -min;
+        for p in cp.iter() {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        let half = 0.5
+            * lerp(self.u_min, self.common.width[0], self.common.width[1]).max(lerp(
+                self.u_max,
+                self.common.width[0],
+                self.common.width[1],
+            ));
+        *output_box = AABB::new(&(min - half), &(max + half));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn straight(kind: CurveType) -> Curve {
+        let cp = [
+            Point::new(-1.0, 0.0, 0.0),
+            Point::new(-1.0 / 3.0, 0.0, 0.0),
+            Point::new(1.0 / 3.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+        ];
+        Curve::new(cp, 0.2, 0.2, kind, Arc::new(Lambertian::new(Color::ones())))
+    }
+
+    fn down(x: f64, y: f64) -> Ray {
+        Ray {
+            orig: Point::new(x, y, 5.0),
+            dire: Vec3::new(0.0, 0.0, -2.0),
+            tm: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_hit_tube() {
+        let curve = straight(CurveType::Tube);
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Color::ones())));
+        assert!(curve.hit(&down(0.3, 0.0), 0.001, INFINITY, &mut rec));
+        assert!((rec.t - 2.5).abs() < 1e-6, "{}", rec.t);
+        assert!((rec.u - 0.65).abs() < 1e-3 && (rec.v - 0.5).abs() < 1e-3);
+        assert!(rec.dpdu.x > 0.0);
+
+        // across the width v runs from 0 to 1
+        assert!(curve.hit(&down(0.3, 0.05), 0.001, INFINITY, &mut rec));
+        assert!((rec.v - 0.5).abs() > 0.2 && (rec.v - 0.5).abs() < 0.3);
+        assert!(!curve.hit(&down(0.3, 0.15), 0.001, INFINITY, &mut rec));
+        assert!(!curve.hit(&down(1.3, 0.0), 0.001, INFINITY, &mut rec));
+        assert!(!curve.hit(&down(0.3, 0.0), 0.001, 2.0, &mut rec));
+    }
+
+    #[test]
+    fn test_hit_ribbon() {
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Color::ones())));
+        let facing = straight(CurveType::Ribbon(
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ));
+        assert!(facing.hit(&down(-0.5, 0.05), 0.001, INFINITY, &mut rec));
+        assert!((rec.normal.z.abs() - 1.0).abs() < 1e-6);
+        // seen edge on, a ribbon has no width
+        let edge_on = straight(CurveType::Ribbon(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ));
+        assert!(!edge_on.hit(&down(-0.5, 0.05), 0.001, INFINITY, &mut rec));
+    }
+}
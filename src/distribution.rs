@@ -0,0 +1,126 @@
+// Piecewise constant densities over [0, 1) and [0, 1)^2 built from tabulated weights, for
+// importance sampling images (pbrt's Distribution1D and Distribution2D).
+
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>, // func.len() + 1 entries from 0 to 1
+    integral: f64, // of the function over [0, 1)
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        assert!(n > 0, "empty distribution");
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // all zero: fall back to uniform
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // x in [0, 1) distributed like the function, its density and the bucket it fell in
+    pub fn sample(&self, xi: f64) -> (f64, f64, usize) {
+        let n = self.func.len();
+        // binary search for the last bucket starting at or below xi
+        let (mut lo, mut hi) = (0, n);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if self.cdf[mid] <= xi {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let i = lo;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (xi - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + offset) / n as f64, self.pdf_bucket(i), i)
+    }
+
+    fn pdf_bucket(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.func.len();
+        self.pdf_bucket(((x * n as f64) as usize).min(n - 1))
+    }
+}
+
+// rows of conditional distributions over u, and a marginal one picking the row over v
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func[row][column], rows along v
+    pub fn new(func: Vec<Vec<f64>>) -> Self {
+        let conditional: Vec<Distribution1D> = func.into_iter().map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    // (u, v) in [0, 1)^2 and its density
+    pub fn sample(&self, xi: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(xi.1);
+        let (u, pdf_u, _) = self.conditional[row].sample(xi.0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let n = self.conditional.len();
+        let row = ((v * n as f64) as usize).min(n - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_1d() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert!((d.integral() - 2.0).abs() < 1e-12);
+        // the bucket of weight 3 covers cdf [1/8, 4/8)
+        let (x, pdf, i) = d.sample(0.25);
+        assert_eq!(i, 1);
+        assert!((x - (0.25 + 1.0 / 12.0)).abs() < 1e-12);
+        assert!((pdf - 1.5).abs() < 1e-12);
+        assert!((d.pdf(x) - pdf).abs() < 1e-12);
+        // never lands in an empty bucket
+        for k in 0..100 {
+            let (x, _, _) = d.sample(k as f64 / 100.0);
+            assert!(d.pdf(x) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs;
+
+// Minimal OpenEXR reader: single part scanline files without compression, with half, float
+// or uint R, G, B (or Y) and A channels. Others are rejected with a message to convert them.
+pub struct ExrImage {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<[f32; 4]>, // linear rgba, rows from the top
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        assert!(self.pos + n <= self.bytes.len(), "truncated EXR");
+        let b = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        b
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn i32(&mut self) -> i32 {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4));
+        i32::from_le_bytes(b)
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8));
+        u64::from_le_bytes(b)
+    }
+
+    // null terminated
+    fn string(&mut self) -> String {
+        let len = self.bytes[self.pos..]
+            .iter()
+            .position(|b| *b == 0)
+            .expect("truncated EXR");
+        let s = String::from_utf8_lossy(self.take(len)).into_owned();
+        self.pos += 1;
+        s
+    }
+
+    // a sample of the given pixel type: 0 uint, 1 half, 2 float
+    fn sample(&mut self, pixel_type: i32) -> f32 {
+        match pixel_type {
+            0 => self.i32() as u32 as f32,
+            1 => {
+                let b = self.take(2);
+                half_to_f32(u16::from_le_bytes([b[0], b[1]]))
+            }
+            _ => f32::from_bits(self.i32() as u32),
+        }
+    }
+}
+
+pub fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa_bits = h & 0x3ff;
+    let mantissa = mantissa_bits as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa_bits == 0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+pub fn load_exr(filename: &str) -> ExrImage {
+    let bytes = fs::read(filename).expect("failed to read the EXR");
+    let mut r = Reader {
+        bytes: &bytes,
+        pos: 0,
+    };
+    assert_eq!(r.i32(), 20000630, "not an EXR");
+    let flags = r.i32();
+    assert_eq!(
+        flags & 0x1a00,
+        0,
+        "tiled, deep and multipart EXRs are not supported"
+    );
+
+    let mut channels = vec![]; // (name, pixel type), in the order they are stored
+    let mut compression = 0;
+    let mut window = [0; 4]; // x min, y min, x max, y max
+    loop {
+        let name = r.string();
+        if name.is_empty() {
+            break;
+        }
+        let _type = r.string();
+        let size = r.i32() as usize;
+        let end = r.pos + size;
+        match name.as_str() {
+            "channels" => loop {
+                let channel = r.string();
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = r.i32();
+                r.take(4); // pLinear and reserved
+                assert!(
+                    r.i32() == 1 && r.i32() == 1,
+                    "subsampled EXRs are not supported"
+                );
+                channels.push((channel, pixel_type));
+            },
+            "compression" => compression = r.u8(),
+            "dataWindow" => {
+                for w in window.iter_mut() {
+                    *w = r.i32();
+                }
+            }
+            _ => {}
+        }
+        r.pos = end;
+    }
+    assert_eq!(
+        compression, 0,
+        "compressed EXRs are not supported, save {} without compression",
+        filename
+    );
+
+    let width = (window[2] - window[0] + 1) as usize;
+    let height = (window[3] - window[1] + 1) as usize;
+    let mut texels = vec![[0.0, 0.0, 0.0, 1.0]; width * height];
+    let offsets: Vec<u64> = (0..height).map(|_| r.u64()).collect();
+    for offset in offsets {
+        r.pos = offset as usize;
+        let y = (r.i32() - window[1]) as usize;
+        let _size = r.i32();
+        for (name, pixel_type) in channels.iter() {
+            let targets: &[usize] = match name.as_str() {
+                "R" => &[0],
+                "G" => &[1],
+                "B" => &[2],
+                "Y" => &[0, 1, 2],
+                "A" => &[3],
+                _ => &[],
+            };
+            for x in 0..width {
+                let value = r.sample(*pixel_type);
+                for c in targets {
+                    texels[y * width + x][*c] = value;
+                }
+            }
+        }
+    }
+    ExrImage {
+        width,
+        height,
+        texels,
+    }
+}
+
+#[allow(clippy::float_cmp)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_to_f32() {
+        let cases = [
+            (0x3c00, 1.0),
+            (0xc000, -2.0),
+            (0x7bff, 65504.0),
+            (0x0001, 2f32.powi(-24)),
+        ];
+        for (h, f) in cases.iter() {
+            assert!((half_to_f32(*h) - f).abs() <= f.abs() * 1e-6);
+        }
+        assert!(half_to_f32(0x7c00).is_infinite());
+    }
+
+    fn f32_to_half(f: f32) -> u16 {
+        // exact for the normal values the test uses
+        let bits = f.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+        sign | (exponent as u16) << 10 | ((bits >> 13) & 0x3ff) as u16
+    }
+
+    #[test]
+    fn test_load_exr() {
+        // 2 x 2 pixels at (3, 5), half R and B, float G, no alpha
+        let (width, height) = (2, 2);
+        let texel = |x: usize, y: usize| [0.5 + x as f32, 0.25 * y as f32, 2.0, 1.0];
+        let attribute = |out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            for s in [name, kind].iter() {
+                out.extend_from_slice(s.as_bytes());
+                out.push(0);
+            }
+            out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            out.extend_from_slice(value);
+        };
+
+        let mut channels = vec![];
+        for (name, pixel_type) in [("B", 1i32), ("G", 2), ("R", 1)].iter() {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&pixel_type.to_le_bytes());
+            channels.extend_from_slice(&[0; 4]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+        let window: Vec<u8> = [3i32, 5, 4, 6]
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&20000630i32.to_le_bytes());
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[0]);
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        // scanlines stored bottom up, found through the offset table
+        let mut lines = vec![];
+        let table_end = bytes.len() + 8 * height;
+        let mut offsets = vec![0; height];
+        for y in (0..height).rev() {
+            offsets[y] = (table_end + lines.len()) as u64;
+            let mut data = vec![];
+            for channel in 0..3 {
+                for x in 0..width {
+                    match channel {
+                        0 => data.extend_from_slice(&f32_to_half(texel(x, y)[2]).to_le_bytes()),
+                        1 => data.extend_from_slice(&texel(x, y)[1].to_le_bytes()),
+                        _ => data.extend_from_slice(&f32_to_half(texel(x, y)[0]).to_le_bytes()),
+                    }
+                }
+            }
+            lines.extend_from_slice(&(5 + y as i32).to_le_bytes());
+            lines.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            lines.extend_from_slice(&data);
+        }
+        for offset in offsets {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        bytes.extend_from_slice(&lines);
+
+        let path = std::env::temp_dir().join(format!("test_load_{}.exr", std::process::id()));
+        fs::write(&path, &bytes).unwrap();
+        let image = load_exr(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((image.width, image.height), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(image.texels[y * width + x], texel(x, y));
+            }
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use crate::aabb::*;
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::phase::PhaseFunction;
+use crate::ray::{Ray, RayDifferential};
 use crate::rtweekend::*;
-use crate::vec3::Point;
 use crate::vec3::Vec3;
+use crate::vec3::{Color, Point};
 use std::sync::Arc;
 use std::vec;
 
@@ -11,11 +12,22 @@ use std::vec;
 pub struct HitRecord {
     pub p: Point, // the point where ray hit surface
     pub normal: Vec3,
+    pub dpdu: Vec3, // surface tangents along u and v
+    pub dpdv: Vec3,
     pub mat_ptr: Arc<dyn Material>,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    pub tint: Color, // per-primitive color (e.g. particles), scales what the material returns
+    pub phase: Option<Arc<dyn PhaseFunction>>, // set instead of a material inside volumes
+    pub outer_ior: f64, // IOR on the other side of a refractive surface, set by the integrator
+    pub wavelength: Option<f64>, // nm, set by the integrator in spectral mode
+    pub time: f64,   // of the ray, set by the integrator for animated textures
+    pub dudx: f64,   // change of (u, v) from one pixel to the next, zero if unknown
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
 }
 
 impl HitRecord {
@@ -23,13 +35,81 @@ impl HitRecord {
         Self {
             p: Point::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             mat_ptr: m,
             t: 0.0,
             u: 0.0,
             v: 0.0,
             front_face: false,
+            tint: Color::ones(),
+            phase: None,
+            outer_ior: 1.0,
+            wavelength: None,
+            time: 0.0,
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
         }
     }
+
+    // assigns the material of a new hit and clears per-primitive attributes of earlier hits
+    pub fn set_material(&mut self, m: Arc<dyn Material>) {
+        self.mat_ptr = m;
+        self.tint = Color::ones();
+        self.phase = None;
+    }
+
+    // a scattering event inside a medium, the medium's albedo goes into tint
+    pub fn set_phase(&mut self, phase: Arc<dyn PhaseFunction>, albedo: Color) {
+        self.phase = Some(phase);
+        self.tint = albedo;
+    }
+
+    // (u, v) derivatives over the screen, from where the neighbouring pixels' rays meet the
+    // tangent plane (pbrt's SurfaceInteraction::ComputeDifferentials)
+    pub fn compute_differentials(&mut self, diff: &RayDifferential) {
+        let n = self.normal;
+        let d = n * self.p;
+        let offset = |r: &Ray| {
+            let denom = n * r.dire;
+            if denom.abs() < 1e-12 {
+                return None;
+            }
+            Some(r.at((d - n * r.orig) / denom) - self.p)
+        };
+        let (dpdx, dpdy) = match (offset(&diff.rx), offset(&diff.ry)) {
+            (Some(dpdx), Some(dpdy)) => (dpdx, dpdy),
+            _ => return,
+        };
+
+        // solve dp = dpdu du + dpdv dv in the two axes the normal is least aligned with
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let det = self.dpdu[a] * self.dpdv[b] - self.dpdv[a] * self.dpdu[b];
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve = |dp: Vec3| {
+            (
+                (self.dpdv[b] * dp[a] - self.dpdv[a] * dp[b]) / det,
+                (self.dpdu[a] * dp[b] - self.dpdu[b] * dp[a]) / det,
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        self.dudx = dudx;
+        self.dvdx = dvdx;
+        self.dudy = dudy;
+        self.dvdy = dvdy;
+    }
+
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = (r.dire * *outward_normal) < 0.0; // ray into the surface
         if self.front_face {
@@ -68,6 +148,18 @@ pub fn get_sphere_uv(p: &Vec3, u: &mut f64, v: &mut f64) {
     *v = (theta + PI / 2.0) / PI;
 }
 
+// derivatives of the point on a sphere along the uv of get_sphere_uv, p being on the unit sphere
+pub fn get_sphere_tangents(p: &Vec3, radius: f64, dpdu: &mut Vec3, dpdv: &mut Vec3) {
+    *dpdu = Vec3::new(p.z, 0.0, -p.x) * (2.0 * PI * radius);
+    let cos_theta = (p.x * p.x + p.z * p.z).sqrt();
+    *dpdv = if cos_theta > 1e-8 {
+        Vec3::new(-p.y * p.x / cos_theta, cos_theta, -p.y * p.z / cos_theta) * (PI * radius)
+    } else {
+        // at the poles dpdu vanishes, any tangent will do
+        Vec3::new(1.0, 0.0, 0.0) * (PI * radius)
+    };
+}
+
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let oc = r.orig - self.center;
@@ -85,12 +177,9 @@ impl Hittable for Sphere {
                 rec.p = r.at(rec.t);
                 let outward_normal = (rec.p - self.center) / self.radius;
                 rec.set_face_normal(r, &outward_normal);
-                get_sphere_uv(
-                    &((rec.p - self.center) / self.radius),
-                    &mut rec.u,
-                    &mut rec.v,
-                );
-                rec.mat_ptr = self.mat_ptr.clone();
+                get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+                get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
+                rec.set_material(self.mat_ptr.clone());
                 return true;
             }
 
@@ -100,12 +189,9 @@ impl Hittable for Sphere {
                 rec.p = r.at(rec.t);
                 let outward_normal = (rec.p - self.center) / self.radius;
                 rec.set_face_normal(r, &outward_normal);
-                get_sphere_uv(
-                    &((rec.p - self.center) / self.radius),
-                    &mut rec.u,
-                    &mut rec.v,
-                );
-                rec.mat_ptr = self.mat_ptr.clone();
+                get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+                get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
+                rec.set_material(self.mat_ptr.clone());
                 return true;
             }
         }
@@ -228,7 +314,9 @@ impl Hittable for MovingSphere {
                 rec.p = r.at(rec.t);
                 let outward_normal = (rec.p - self.center(r.tm)) / self.radius;
                 rec.set_face_normal(r, &outward_normal);
-                rec.mat_ptr = self.mat_ptr.clone();
+                get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+                get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
+                rec.set_material(self.mat_ptr.clone());
                 return true;
             }
 
@@ -238,7 +326,9 @@ impl Hittable for MovingSphere {
                 rec.p = r.at(rec.t);
                 let outward_normal = (rec.p - self.center(r.tm)) / self.radius;
                 rec.set_face_normal(r, &outward_normal);
-                rec.mat_ptr = self.mat_ptr.clone();
+                get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+                get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
+                rec.set_material(self.mat_ptr.clone());
                 return true;
             }
         }
@@ -284,9 +374,8 @@ impl Hittable for Translate {
             return false;
         }
 
+        // the normal and front_face of the moved hit stay as they are
         rec.p += self.offset;
-        let normal = rec.normal;
-        rec.set_face_normal(&moved_r, &normal);
 
         true
     }
@@ -303,6 +392,31 @@ impl Hittable for Translate {
     }
 }
 
+// turns a primitive inside out, e.g. the sides of a box that face the negative axes
+pub struct FlipFace {
+    ptr: Arc<dyn Hittable>,
+}
+
+impl FlipFace {
+    pub fn new(p: Arc<dyn Hittable>) -> Self {
+        Self { ptr: p }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.ptr.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        rec.front_face = !rec.front_face;
+        true
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64, output_box: &mut AABB) -> bool {
+        self.ptr.bounding_box(t0, t1, output_box)
+    }
+}
+
 pub struct RotateY {
     pub ptr: Arc<dyn Hittable>,
     pub sin_theta: f64,
@@ -380,8 +494,18 @@ impl Hittable for RotateY {
         normal[0] = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
         normal[2] = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
 
+        let mut dpdu = rec.dpdu;
+        let mut dpdv = rec.dpdv;
+        dpdu[0] = self.cos_theta * rec.dpdu[0] + self.sin_theta * rec.dpdu[2];
+        dpdu[2] = -self.sin_theta * rec.dpdu[0] + self.cos_theta * rec.dpdu[2];
+        dpdv[0] = self.cos_theta * rec.dpdv[0] + self.sin_theta * rec.dpdv[2];
+        dpdv[2] = -self.sin_theta * rec.dpdv[0] + self.cos_theta * rec.dpdv[2];
+
         rec.p = p;
-        rec.set_face_normal(&rotated_r, &normal);
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        // already facing the ray, front_face is unchanged by the rotation
+        rec.normal = normal;
 
         true
     }
@@ -390,3 +514,64 @@ impl Hittable for RotateY {
         self.hasbox
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XYRect;
+    use crate::material::Lambertian;
+
+    fn toward(orig: Point, dire: Vec3) -> Ray {
+        Ray {
+            orig,
+            dire,
+            tm: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_front_face_through_transforms() {
+        // a unit sphere moved to (2, 0, 0) and turned around y to end up at (0, 0, -2)
+        let mat = Arc::new(Lambertian::new(Color::ones()));
+        let sphere = Arc::new(Sphere::new(Point::zero(), 1.0, mat.clone()));
+        let moved = Arc::new(Translate::new(sphere, &Vec3::new(2.0, 0.0, 0.0)));
+        let turned = RotateY::new(moved, 90.0);
+        let down_z = Vec3::new(0.0, 0.0, -1.0);
+        let mut rec = HitRecord::new(mat.clone());
+
+        assert!(turned.hit(
+            &toward(Point::new(0.0, 0.0, 5.0), down_z),
+            0.001,
+            INFINITY,
+            &mut rec
+        ));
+        assert!(rec.front_face);
+        assert!((rec.p - Point::new(0.0, 0.0, -1.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        assert!(turned.hit(
+            &toward(Point::new(0.0, 0.0, -2.0), down_z),
+            0.001,
+            INFINITY,
+            &mut rec
+        ));
+        assert!(!rec.front_face);
+        assert!((rec.p - Point::new(0.0, 0.0, -3.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        // a flipped rectangle, like the back of a box, keeps its flipped side
+        let rect = Arc::new(XYRect::new(-1.0, 1.0, -1.0, 1.0, 0.0, mat));
+        let back = Arc::new(FlipFace::new(rect));
+        let turned = RotateY::new(
+            Arc::new(Translate::new(back, &Vec3::new(0.0, 0.0, 1.0))),
+            30.0,
+        );
+        assert!(turned.hit(
+            &toward(Point::new(0.0, 0.0, 5.0), down_z),
+            0.001,
+            INFINITY,
+            &mut rec
+        ));
+        assert!(!rec.front_face);
+    }
+}
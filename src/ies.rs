@@ -0,0 +1,147 @@
+use crate::rtweekend::clamp;
+use std::fs;
+
+// Luminous intensity distribution of a real luminaire from an IES LM-63 photometric file.
+// Only type C photometry, the common kind: vertical angles from straight down (0) to
+// straight up (180), horizontal angles around the vertical axis. Tilt data is ignored.
+pub struct IesProfile {
+    vertical: Vec<f64>,     // degrees, increasing
+    horizontal: Vec<f64>,   // degrees, increasing, the last one gives the symmetry
+    candela: Vec<Vec<f64>>, // [horizontal][vertical], divided by the largest value
+}
+
+// the segment of sorted `angles` containing x and how far along it x is
+fn segment(angles: &[f64], x: f64) -> (usize, f64) {
+    if angles.len() == 1 || x <= angles[0] {
+        return (0, 0.0);
+    }
+    let i = angles
+        .iter()
+        .rposition(|a| *a <= x)
+        .unwrap()
+        .min(angles.len() - 2);
+    (
+        i,
+        clamp((x - angles[i]) / (angles[i + 1] - angles[i]), 0.0, 1.0),
+    )
+}
+
+impl IesProfile {
+    pub fn load(filename: &str) -> Self {
+        Self::parse(&fs::read_to_string(filename).expect("failed to read the IES file"))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .expect("not an IES file, no TILT line");
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f64>().expect("bad number in IES file"));
+        let mut next = || numbers.next().expect("truncated IES file");
+
+        if tilt.trim() == "TILT=INCLUDE" {
+            let _geometry = next();
+            let pairs = next() as usize;
+            for _ in 0..2 * pairs {
+                next();
+            }
+        }
+        let _lamps = next();
+        let _lumens = next();
+        let multiplier = next();
+        let vertical_count = next() as usize;
+        let horizontal_count = next() as usize;
+        assert_eq!(next() as i32, 1, "only type C IES photometry is supported");
+        for _ in 0..7 {
+            next(); // units, width, length, height, ballast factor, future use, watts
+        }
+        let vertical: Vec<f64> = (0..vertical_count).map(|_| next()).collect();
+        let horizontal: Vec<f64> = (0..horizontal_count).map(|_| next()).collect();
+        let mut candela: Vec<Vec<f64>> = (0..horizontal_count)
+            .map(|_| (0..vertical_count).map(|_| next() * multiplier).collect())
+            .collect();
+
+        let max = candela
+            .iter()
+            .flat_map(|row| row.iter())
+            .fold(0.0_f64, |m, c| m.max(*c));
+        if max > 0.0 {
+            for c in candela.iter_mut().flat_map(|row| row.iter_mut()) {
+                *c /= max;
+            }
+        }
+        Self {
+            vertical,
+            horizontal,
+            candela,
+        }
+    }
+
+    // intensity relative to the brightest direction, angles in degrees
+    pub fn value(&self, vertical: f64, horizontal: f64) -> f64 {
+        let first = self.vertical[0];
+        let last = self.vertical[self.vertical.len() - 1];
+        if vertical < first || vertical > last {
+            return 0.0;
+        }
+
+        // unfold the symmetry of the horizontal angles given
+        let mut h = horizontal.rem_euclid(360.0);
+        match self.horizontal[self.horizontal.len() - 1] as i32 {
+            90 => {
+                if h > 180.0 {
+                    h = 360.0 - h;
+                }
+                if h > 90.0 {
+                    h = 180.0 - h;
+                }
+            }
+            180 if h > 180.0 => h = 360.0 - h,
+            _ => {}
+        }
+
+        let (j, fv) = segment(&self.vertical, vertical);
+        let (i, fh) = segment(&self.horizontal, h);
+        let at = |i: usize, j: usize| {
+            let row = &self.candela[i.min(self.horizontal.len() - 1)];
+            row[j.min(self.vertical.len() - 1)]
+        };
+        let below = at(i, j) * (1.0 - fv) + at(i, j + 1) * fv;
+        let above = at(i + 1, j) * (1.0 - fv) + at(i + 1, j + 1) * fv;
+        below * (1.0 - fh) + above * fh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        // a downlight lighting the lower hemisphere, dimmer toward 90 degrees horizontally
+        let profile = IesProfile::parse(
+            "IESNA:LM-63-2002\n\
+             [TEST] downlight\n\
+             TILT=NONE\n\
+             1 1000 2 3 2 1 1 0 0 0\n\
+             1 1 20\n\
+             0 45 90\n\
+             0, 90\n\
+             100 50 0\n\
+             50 25 0\n",
+        );
+        assert!((profile.value(0.0, 0.0) - 1.0).abs() < 1e-12);
+        assert!((profile.value(22.5, 0.0) - 0.75).abs() < 1e-12);
+        assert!((profile.value(45.0, 45.0) - 0.375).abs() < 1e-12);
+        // quadrant symmetry
+        assert!((profile.value(45.0, 135.0) - 0.375).abs() < 1e-12);
+        assert!((profile.value(45.0, 270.0) - 0.25).abs() < 1e-12);
+        assert!(profile.value(120.0, 0.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,688 @@
+use crate::aabb::AABB;
+use crate::hittable::*;
+use crate::ies::IesProfile;
+use crate::light_bvh::LightBVH;
+use crate::material::{luminance, Material};
+use crate::onb::ONB;
+use crate::ray::Ray;
+use crate::rtweekend::*;
+use crate::texture::Texture;
+use crate::vec3::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// light arriving at a point from one sample of a light
+pub struct LightSample {
+    pub dir: Vec3,       // unit, toward the light
+    pub distance: f64,   // to the sampled point, INFINITY for distant lights
+    pub radiance: Color, // arriving along dir, for delta lights the irradiance facing it
+    pub pdf: f64,        // in solid angle, 1 for delta lights
+    pub delta: bool,
+}
+
+// Lights that the integrator samples at every diffuse or glossy surface. Delta lights (point,
+// spot, directional) have no area and are only ever seen this way, so they don't light
+// mirrors, glass or the inside of media.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: &Point) -> Option<LightSample>;
+
+    // density of sample() at `p` choosing `dir`, for lights that rays can hit
+    fn pdf(&self, _p: &Point, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    // the material on the light's surface, by which rays hitting it are recognised
+    fn material(&self) -> Option<Arc<dyn Material>> {
+        None
+    }
+
+    // rough luminous power, for picking bright lights more often than dim ones
+    fn power(&self) -> f64;
+
+    // where the light is, None for lights infinitely far away
+    fn bounds(&self) -> Option<AABB>;
+}
+
+fn point_bounds(p: &Point) -> Option<AABB> {
+    Some(AABB::new(p, p))
+}
+
+// angles in degrees of `dir` (unit) in a luminaire's frame, where w is vertical angle 0 and
+// u horizontal angle 0, and the profile's intensity there
+fn profile_value(profile: &IesProfile, frame: &ONB, dir: &Vec3) -> f64 {
+    let local = frame.to_local(dir);
+    let vertical = clamp(local.z, -1.0, 1.0).acos().to_degrees();
+    let horizontal = local.y.atan2(local.x).to_degrees();
+    profile.value(vertical, horizontal)
+}
+
+// an orthonormal frame with w along `axis` (unit), u as close to `toward` as possible
+fn frame(axis: Vec3, toward: Vec3) -> ONB {
+    let mut frame = ONB::build_from_w(&axis);
+    let u = toward - axis * (toward * axis);
+    if u.squared_length() > 1e-12 {
+        frame.u = u.unit();
+        frame.v = Vec3::cross(frame.w, frame.u);
+    }
+    frame
+}
+
+// Light from a point, equal in all directions or shaped by a photometric profile whose
+// vertical angle 0 is along `aim`. Intensity is radiant intensity in the brightest direction.
+pub struct PointLight {
+    pub position: Point,
+    pub intensity: Color,
+    profile: Option<(Arc<IesProfile>, ONB)>,
+}
+
+impl PointLight {
+    pub fn new(position: Point, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+
+    pub fn new_with_profile(
+        position: Point,
+        intensity: Color,
+        profile: Arc<IesProfile>,
+        aim: Vec3,
+    ) -> Self {
+        Self {
+            position,
+            intensity,
+            profile: Some((profile, ONB::build_from_w(&aim))),
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let d = self.position - *p;
+        let distance = d.length();
+        let dir = d / distance;
+        let intensity = match &self.profile {
+            Some((profile, frame)) => self.intensity * profile_value(profile, frame, &-dir),
+            None => self.intensity,
+        };
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: intensity / (distance * distance),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        4.0 * PI * luminance(&self.intensity)
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        point_bounds(&self.position)
+    }
+}
+
+// Point light in a cone around the line toward `target`. Full intensity within `falloff`
+// degrees of the axis, fading smoothly to nothing at `cutoff` degrees.
+pub struct SpotLight {
+    pub position: Point,
+    pub intensity: Color,
+    axis: Vec3,
+    cos_cutoff: f64,
+    cos_falloff: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point,
+        target: Point,
+        intensity: Color,
+        cutoff: f64,
+        falloff: f64,
+    ) -> Self {
+        Self {
+            position,
+            intensity,
+            axis: (target - position).unit(),
+            cos_cutoff: degrees_to_radians(cutoff).cos(),
+            cos_falloff: degrees_to_radians(falloff.min(cutoff)).cos(),
+        }
+    }
+
+    fn falloff(&self, cos: f64) -> f64 {
+        if cos >= self.cos_falloff {
+            return 1.0;
+        }
+        let t = clamp(
+            (cos - self.cos_cutoff) / (self.cos_falloff - self.cos_cutoff),
+            0.0,
+            1.0,
+        );
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let d = self.position - *p;
+        let distance = d.length();
+        let dir = d / distance;
+        let falloff = self.falloff(-dir * self.axis);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: self.intensity * (falloff / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+
+    fn power(&self) -> f64 {
+        let solid_angle = 2.0 * PI * (1.0 - 0.5 * (self.cos_cutoff + self.cos_falloff));
+        solid_angle * luminance(&self.intensity)
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        point_bounds(&self.position)
+    }
+}
+
+// Parallel light from infinitely far away, like the sun, travelling along `direction`.
+// Irradiance is measured on a surface facing it.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: direction.unit(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Point) -> Option<LightSample> {
+        Some(LightSample {
+            dir: -self.direction,
+            distance: INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+
+    // per unit area, lights at infinity aren't compared by power
+    fn power(&self) -> f64 {
+        luminance(&self.irradiance)
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        None
+    }
+}
+
+// The emitting side of an area light, also the material on its surface. Radiance is the
+// texture at (u, v), shaped by the profile if there is one.
+struct Emitter {
+    texture: Arc<dyn Texture>,
+    profile: Option<Arc<IesProfile>>,
+    frame: ONB, // w is the normal
+}
+
+impl Emitter {
+    // radiance leaving toward `dir` (unit)
+    fn radiance(&self, u: f64, v: f64, p: &Point, dir: &Vec3) -> Color {
+        if *dir * self.frame.w <= 0.0 {
+            return Color::zero();
+        }
+        let emitted = self.texture.value(u, v, p);
+        match &self.profile {
+            Some(profile) => emitted * profile_value(profile, &self.frame, dir),
+            None => emitted,
+        }
+    }
+}
+
+impl Material for Emitter {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.radiance(rec.u, rec.v, &rec.p, &-r_in.dire.unit())
+    }
+}
+
+// A parallelogram from `corner` along `edge_u` and `edge_v` that emits from the side
+// edge_u x edge_v points to, with (u, v) running over it for the texture. Add it to the
+// world as well as the lights so that it is seen too. A profile's vertical angle 0 is the
+// normal and horizontal angle 0 is along edge_u.
+pub struct AreaLight {
+    corner: Point,
+    edge_u: Vec3,
+    edge_v: Vec3,
+    w: Vec3, // normal / |normal|^2, for the coordinates of hits
+    area: f64,
+    emitter: Arc<Emitter>,
+}
+
+impl AreaLight {
+    pub fn new(corner: Point, edge_u: Vec3, edge_v: Vec3, emit: Arc<dyn Texture>) -> Self {
+        Self::build(corner, edge_u, edge_v, emit, None)
+    }
+
+    pub fn new_with_profile(
+        corner: Point,
+        edge_u: Vec3,
+        edge_v: Vec3,
+        emit: Arc<dyn Texture>,
+        profile: Arc<IesProfile>,
+    ) -> Self {
+        Self::build(corner, edge_u, edge_v, emit, Some(profile))
+    }
+
+    fn build(
+        corner: Point,
+        edge_u: Vec3,
+        edge_v: Vec3,
+        texture: Arc<dyn Texture>,
+        profile: Option<Arc<IesProfile>>,
+    ) -> Self {
+        let n = Vec3::cross(edge_u, edge_v);
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+            w: n / n.squared_length(),
+            area: n.length(),
+            emitter: Arc::new(Emitter {
+                texture,
+                profile,
+                frame: frame(n.unit(), edge_u),
+            }),
+        }
+    }
+
+    // t and (u, v) where `r` crosses the light
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let normal = self.emitter.frame.w;
+        let denom = normal * r.dire;
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = normal * (self.corner - r.orig) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let planar = r.at(t) - self.corner;
+        let u = self.w * Vec3::cross(planar, self.edge_v);
+        let v = self.w * Vec3::cross(self.edge_u, planar);
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some((t, u, v))
+    }
+}
+
+impl Hittable for AreaLight {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, u, v) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        rec.u = u;
+        rec.v = v;
+        rec.dpdu = self.edge_u;
+        rec.dpdv = self.edge_v;
+        rec.t = t;
+        rec.set_face_normal(r, &self.emitter.frame.w);
+        rec.set_material(self.emitter.clone());
+        rec.p = r.at(t);
+        true
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
+        let corners = [
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ];
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for c in corners.iter() {
+            for i in 0..3 {
+                min[i] = min[i].min(c[i] - 0.0001);
+                max[i] = max[i].max(c[i] + 0.0001);
+            }
+        }
+        *output_box = AABB::new(&min, &max);
+        true
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let (u, v) = (random_double(0.0, 1.0), random_double(0.0, 1.0));
+        let q = self.corner + self.edge_u * u + self.edge_v * v;
+        let to_light = q - *p;
+        let distance_squared = to_light.squared_length();
+        let distance = distance_squared.sqrt();
+        let dir = to_light / distance;
+        let cosine = -dir * self.emitter.frame.w;
+        if cosine <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: self.emitter.radiance(u, v, &q, &-dir),
+            pdf: distance_squared / (cosine * self.area),
+            delta: false,
+        })
+    }
+
+    fn pdf(&self, p: &Point, dir: &Vec3) -> f64 {
+        let r = Ray {
+            orig: *p,
+            dire: *dir,
+            tm: 0.0,
+        };
+        let cosine = -*dir * self.emitter.frame.w / dir.length();
+        match self.intersect(&r, 0.0001, INFINITY) {
+            Some((t, _, _)) if cosine > 0.0 => dir.squared_length() * t * t / (cosine * self.area),
+            _ => 0.0,
+        }
+    }
+
+    fn material(&self) -> Option<Arc<dyn Material>> {
+        Some(self.emitter.clone())
+    }
+
+    // from the texture's average over a grid
+    fn power(&self) -> f64 {
+        let mut total = 0.0;
+        for i in 0..4 {
+            for j in 0..4 {
+                let (u, v) = ((i as f64 + 0.5) / 4.0, (j as f64 + 0.5) / 4.0);
+                let p = self.corner + self.edge_u * u + self.edge_v * v;
+                total += luminance(&self.emitter.texture.value(u, v, &p));
+            }
+        }
+        PI * self.area * total / 16.0
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        let mut output_box = AABB::new(&self.corner, &self.corner);
+        self.bounding_box(0.0, 0.0, &mut output_box);
+        Some(output_box)
+    }
+}
+
+// Spheres with an emitting material such as DiffuseLight, sampled within the cone they fill
+// as seen from the point being lit. Add them to the world as well. Lights are recognised by
+// their material, so give each sphere its own.
+impl Light for Sphere {
+    fn sample(&self, p: &Point) -> Option<LightSample> {
+        let d = self.center - *p;
+        let distance_squared = d.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return None;
+        }
+        let cos_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let dir = ONB::build_from_w(&d).local(&random_in_cone(cos_max));
+        let r = Ray {
+            orig: *p,
+            dire: dir,
+            tm: 0.0,
+        };
+        let mut rec = HitRecord::new(self.mat_ptr.clone());
+        if !self.hit(&r, 0.0001, INFINITY, &mut rec) {
+            return None;
+        }
+        Some(LightSample {
+            dir,
+            distance: rec.t,
+            radiance: self.mat_ptr.emitted(&r, &rec),
+            pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+            delta: false,
+        })
+    }
+
+    fn pdf(&self, p: &Point, dir: &Vec3) -> f64 {
+        let d = self.center - *p;
+        let distance_squared = d.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return 0.0;
+        }
+        let cos_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        if dir.unit() * d.unit() < cos_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * (1.0 - cos_max))
+    }
+
+    fn material(&self) -> Option<Arc<dyn Material>> {
+        Some(self.mat_ptr.clone())
+    }
+
+    // from the radiance at the top
+    fn power(&self) -> f64 {
+        let r = Ray {
+            orig: self.center + Vec3::new(0.0, 2.0 * self.radius, 0.0),
+            dire: Vec3::new(0.0, -1.0, 0.0),
+            tm: 0.0,
+        };
+        let mut rec = HitRecord::new(self.mat_ptr.clone());
+        if !self.hit(&r, 0.0001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let radiance = self.mat_ptr.emitted(&r, &rec);
+        PI * 4.0 * PI * self.radius * self.radius * luminance(&radiance)
+    }
+
+    fn bounds(&self) -> Option<AABB> {
+        let mut output_box = AABB::new(&self.center, &self.center);
+        self.bounding_box(0.0, 0.0, &mut output_box);
+        Some(output_box)
+    }
+}
+
+// The lights of a scene. Lights with a place in it are picked through a LightBVH, by their
+// power over their squared distance to the point being lit, which keeps scenes with many
+// lights from getting noisier the more there are. Lights at infinity are picked uniformly
+// in their share of the count. The hierarchy is made by build(), once all lights are in.
+pub struct LightList {
+    lights: Vec<Arc<dyn Light>>,
+    infinite: Vec<usize>,
+    by_material: HashMap<usize, Vec<usize>>, // lights with each surface material
+    bounded: Vec<(usize, AABB, f64)>,        // (index, bounds, power) of the others
+    bvh: Option<LightBVH>,
+}
+
+// identifies the surface a ray hit by the address of its material
+pub fn material_id(material: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(material) as *const u8 as usize
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self {
+            lights: vec![],
+            infinite: vec![],
+            by_material: HashMap::new(),
+            bounded: vec![],
+            bvh: None,
+        }
+    }
+
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        let index = self.lights.len();
+        if let Some(material) = light.material() {
+            self.by_material
+                .entry(material_id(&material))
+                .or_default()
+                .push(index);
+        }
+        match light.bounds() {
+            Some(bounds) => self.bounded.push((index, bounds, light.power())),
+            None => self.infinite.push(index),
+        }
+        self.lights.push(light);
+        self.bvh = None;
+    }
+
+    // makes the hierarchy over the lights added so far, before any pick
+    pub fn build(&mut self) {
+        self.bvh = Some(LightBVH::new(self.bounded.clone(), self.lights.len()));
+    }
+
+    fn bvh(&self) -> &LightBVH {
+        self.bvh
+            .as_ref()
+            .expect("LightList::build must be called after the last add")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    fn infinite_share(&self) -> f64 {
+        self.infinite.len() as f64 / self.lights.len() as f64
+    }
+
+    // a light for `p` and the probability of picking it
+    pub fn pick(&self, p: &Point) -> Option<(&dyn Light, f64)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let share = self.infinite_share();
+        if random_double(0.0, 1.0) < share {
+            let i = random_int(0, self.infinite.len() as i32 - 1) as usize;
+            return Some((
+                &*self.lights[self.infinite[i]],
+                1.0 / self.lights.len() as f64,
+            ));
+        }
+        let (i, probability) = self.bvh().sample(p)?;
+        Some((&*self.lights[i], (1.0 - share) * probability))
+    }
+
+    // probability of pick() at `p` choosing light `i`
+    fn probability(&self, i: usize, p: &Point) -> f64 {
+        if self.infinite.contains(&i) {
+            1.0 / self.lights.len() as f64
+        } else {
+            (1.0 - self.infinite_share()) * self.bvh().pdf(i, p)
+        }
+    }
+
+    // density of picking and sampling, from `p`, the light whose surface a ray along `dir`
+    // hit, zero for emitters that aren't in the list
+    pub fn pdf_hit(&self, material: usize, p: &Point, dir: &Vec3) -> f64 {
+        match self.by_material.get(&material) {
+            Some(lights) => lights
+                .iter()
+                .map(|&i| match self.lights[i].pdf(p, dir) {
+                    pdf if pdf > 0.0 => self.probability(i, p) * pdf,
+                    _ => 0.0,
+                })
+                .sum(),
+            None => 0.0,
+        }
+    }
+}
+
+impl Default for LightList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::SolidColor;
+
+    #[test]
+    fn test_spot_falloff() {
+        let spot = SpotLight::new(
+            Point::new(0.0, 2.0, 0.0),
+            Point::zero(),
+            Color::ones(),
+            30.0,
+            20.0,
+        );
+        let under = spot.sample(&Point::zero()).unwrap();
+        assert!((under.radiance.x - 0.25).abs() < 1e-12);
+        assert!(spot.sample(&Point::new(2.0, 0.0, 0.0)).is_none());
+        let edge = spot.sample(&Point::new(2.0 * 25f64.to_radians().tan(), 0.0, 0.0));
+        let falloff = edge.unwrap().radiance.x * (2.0 / 25f64.to_radians().cos()).powi(2);
+        // halfway between the angles is past halfway on the smoothstep
+        assert!((falloff - 0.57).abs() < 0.01, "{}", falloff);
+    }
+
+    #[test]
+    fn test_area_light_pdf() {
+        // 2 x 1 panel at y = 3 facing down
+        let light = AreaLight::new(
+            Point::new(-1.0, 3.0, -0.5),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Arc::new(SolidColor::new(Color::ones())),
+        );
+        let p = Point::new(0.3, 0.0, 0.1);
+        for _ in 0..100 {
+            let s = light.sample(&p).unwrap();
+            assert!((light.pdf(&p, &s.dir) - s.pdf).abs() < 1e-9 * s.pdf);
+            assert_eq!(s.radiance, Color::ones());
+        }
+        // seen from above it's dark and can't be sampled
+        assert!(light.sample(&Point::new(0.0, 5.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_light_list_pick() {
+        // a grid of point lights and a sun, built once after all the adds
+        let mut lights = LightList::new();
+        for i in 0..2000 {
+            let position = Point::new((i % 50) as f64, 1.0, (i / 50) as f64);
+            lights.add(Arc::new(PointLight::new(position, Color::ones())));
+        }
+        lights.add(Arc::new(DirectionalLight::new(
+            Vec3::new(0.0, -1.0, 0.0),
+            Color::ones(),
+        )));
+        lights.build();
+
+        let p = Point::new(10.0, 0.0, 10.0);
+        let total: f64 = (0..2001).map(|i| lights.probability(i, &p)).sum();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+        // lights are told apart by address
+        let address = |l: &dyn Light| l as *const dyn Light as *const u8;
+        for _ in 0..100 {
+            let (light, probability) = lights.pick(&p).unwrap();
+            let i = lights
+                .lights
+                .iter()
+                .position(|l| address(&**l) == address(light))
+                .unwrap();
+            assert!((lights.probability(i, &p) - probability).abs() < 1e-12);
+        }
+    }
+}
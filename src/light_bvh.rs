@@ -0,0 +1,173 @@
+use crate::aabb::*;
+use crate::rtweekend::random_double;
+use crate::vec3::*;
+
+struct LightNode {
+    bounds: AABB,
+    power: f64,
+    children: Option<(usize, usize)>,
+    light: usize, // for leaves
+}
+
+// Bounding volume hierarchy over the lights that have a place in the scene, for picking them
+// in proportion to a rough estimate of what they give a point: their power over the squared
+// distance to them. Picking walks down from the root, choosing between the two children by
+// that estimate, so that the many lights far away cost little and add little noise.
+pub struct LightBVH {
+    nodes: Vec<LightNode>,
+    trails: Vec<(u64, u32)>, // per light, the branches from the root (bit set for right)
+}
+
+fn union(boxes: &[(usize, AABB, f64)]) -> AABB {
+    boxes
+        .iter()
+        .skip(1)
+        .fold(boxes[0].1.clone(), |b, (_, other, _)| {
+            surrounding_box(&b, other)
+        })
+}
+
+fn centroid(b: &AABB) -> Point {
+    (b._min + b._max) * 0.5
+}
+
+impl LightBVH {
+    // (light index, bounds, power) of each light, indices below `count`
+    pub fn new(lights: Vec<(usize, AABB, f64)>, count: usize) -> Self {
+        let mut bvh = Self {
+            nodes: vec![],
+            trails: vec![(0, 0); count],
+        };
+        let mut lights = lights;
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(&mut self, lights: &mut [(usize, AABB, f64)], trail: u64, depth: u32) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(LightNode {
+            bounds: union(lights),
+            power: lights.iter().map(|l| l.2).sum(),
+            children: None,
+            light: lights[0].0,
+        });
+        if lights.len() == 1 {
+            self.trails[lights[0].0] = (trail, depth);
+            return index;
+        }
+
+        // split at the median along the axis the centers spread over most
+        let mut centers = AABB::new(&centroid(&lights[0].1), &centroid(&lights[0].1));
+        for l in lights.iter() {
+            let c = centroid(&l.1);
+            centers = surrounding_box(&centers, &AABB::new(&c, &c));
+        }
+        let extent = centers._max - centers._min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        lights.sort_by(|a, b| {
+            centroid(&a.1)[axis]
+                .partial_cmp(&centroid(&b.1)[axis])
+                .unwrap()
+        });
+        let mid = lights.len() / 2;
+        let (left, right) = lights.split_at_mut(mid);
+        let left = self.build(left, trail, depth + 1);
+        let right = self.build(right, trail | 1 << depth, depth + 1);
+        self.nodes[index].children = Some((left, right));
+        index
+    }
+
+    fn importance(&self, node: usize, p: &Point) -> f64 {
+        let node = &self.nodes[node];
+        let b = &node.bounds;
+        let mut distance_squared = 0.0;
+        for i in 0..3 {
+            let d = (b._min[i] - p[i]).max(p[i] - b._max[i]).max(0.0);
+            distance_squared += d * d;
+        }
+        // don't let points close to or inside a cluster blow up
+        let size_squared = (b._max - b._min).squared_length() / 4.0;
+        node.power / distance_squared.max(size_squared).max(1e-8)
+    }
+
+    // probability of going to the left child of `node` from `p`
+    fn left_probability(&self, node: usize, p: &Point) -> f64 {
+        let (left, right) = self.nodes[node].children.unwrap();
+        let (l, r) = (self.importance(left, p), self.importance(right, p));
+        if l + r > 0.0 {
+            l / (l + r)
+        } else {
+            0.5
+        }
+    }
+
+    // a light for `p` and the probability of picking it
+    pub fn sample(&self, p: &Point) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let (mut node, mut probability) = (0, 1.0);
+        while let Some((left, right)) = self.nodes[node].children {
+            let pl = self.left_probability(node, p);
+            if random_double(0.0, 1.0) < pl {
+                node = left;
+                probability *= pl;
+            } else {
+                node = right;
+                probability *= 1.0 - pl;
+            }
+        }
+        Some((self.nodes[node].light, probability))
+    }
+
+    // probability of sample() at `p` picking `light`
+    pub fn pdf(&self, light: usize, p: &Point) -> f64 {
+        let (trail, depth) = self.trails[light];
+        let (mut node, mut probability) = (0, 1.0);
+        for level in 0..depth {
+            let (left, right) = self.nodes[node].children.unwrap();
+            let pl = self.left_probability(node, p);
+            if trail & 1 << level == 0 {
+                node = left;
+                probability *= pl;
+            } else {
+                node = right;
+                probability *= 1.0 - pl;
+            }
+        }
+        probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_near_lights() {
+        // a row of equal lights, p next to the first
+        let lights: Vec<(usize, AABB, f64)> = (0..9)
+            .map(|i| {
+                let c = Point::new(i as f64 * 2.0, 0.0, 0.0);
+                (i, AABB::new(&c, &c), 1.0)
+            })
+            .collect();
+        let bvh = LightBVH::new(lights, 9);
+        let p = Point::new(0.0, 0.5, 0.0);
+        let total: f64 = (0..9).map(|i| bvh.pdf(i, &p)).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        assert!(bvh.pdf(0, &p) > 0.5);
+        for _ in 0..100 {
+            let (i, probability) = bvh.sample(&p).unwrap();
+            assert!((bvh.pdf(i, &p) - probability).abs() < 1e-12);
+        }
+    }
+}
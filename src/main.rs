@@ -1,38 +1,89 @@
 mod aabb;
 mod aarect;
+mod alpha_mask;
+mod background;
+mod bezier;
 mod box6;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
+mod curve;
+mod distribution;
+mod exr;
+mod heterogeneous_medium;
 mod hittable;
+mod ies;
+mod light;
+mod light_bvh;
 mod material;
+mod measured;
+mod medium;
+mod mesh;
+mod microfacet;
+mod normal_map;
+mod onb;
 mod perlin;
+mod phase;
+mod principled;
+mod procedural;
 mod ray;
 mod rtweekend;
+mod sky;
+mod spectrum;
+mod sphere_set;
+mod subsurface;
 mod texture;
+mod texture_ops;
+mod thin_film;
 mod triangle;
 #[allow(clippy::float_cmp)]
 mod vec3;
-use image::{ImageBuffer, RgbImage};
+use image::{DynamicImage, ImageBuffer, RgbImage, RgbaImage};
 use indicatif::ProgressBar;
 use std::sync::Arc;
 
 pub use aarect::*;
+pub use alpha_mask::*;
+pub use background::*;
+pub use bezier::*;
 pub use box6::*;
 pub use bvh::*;
 pub use camera::*;
-pub use color::{ray_color, write_color};
+pub use color::{ray_color, ray_color_spectral, write_color, Scene};
 pub use constant_medium::*;
+pub use curve::*;
+pub use distribution::*;
+pub use exr::*;
+pub use heterogeneous_medium::*;
 pub use hittable::*;
+pub use ies::*;
+pub use light::*;
+pub use light_bvh::*;
 pub use material::*;
+pub use measured::*;
+pub use medium::*;
+pub use mesh::*;
+pub use microfacet::*;
+pub use normal_map::*;
+pub use onb::*;
+pub use phase::*;
+pub use principled::*;
+pub use procedural::*;
 pub use ray::Ray;
 pub use rtweekend::*;
+pub use sky::*;
+pub use spectrum::*;
+pub use sphere_set::*;
 pub use std::sync::mpsc::channel;
 pub use std::thread;
+pub use subsurface::*;
 pub use texture::*;
+pub use texture_ops::*;
+pub use thin_film::*;
 pub use threadpool::ThreadPool;
 pub use triangle::*;
+pub use vec3::random_unit_vector;
 pub use vec3::Color;
 pub use vec3::Point;
 pub use vec3::Vec3;
@@ -59,21 +110,23 @@ fn main() {
     let mut dist_to_focus = 10.0;
     let mut aperture = 0.0;
     let mut vfov = 40.0;
-    let background;
+    let mut spectral = false;
+    let background: Arc<dyn Background>;
+    let mut lights = LightList::new();
     let mut samples_per_pixel = 64;
 
     let scene = 11;
     match scene {
         1 => {
             world = random_scene();
-            background = Color::new(0.7, 0.8, 1.0);
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
             lookfrom = Point::new(13.0, 2.0, 3.0);
             lookat = Point::new(0.0, 0.0, 0.0);
             vfov = 20.0;
             aperture = 0.1;
         }
         2 => {
-            background = Color::new(0.7, 0.8, 1.0);
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
             lookfrom = Point::new(13.0, 2.0, 3.0);
             lookat = Point::new(0.0, 0.0, 0.0);
             vfov = 20.0;
@@ -81,19 +134,21 @@ fn main() {
         3 => {
             world = simple_light();
             samples_per_pixel = 400;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
             lookfrom = Point::new(26.0, 3.0, 6.0);
             lookat = Point::new(0.0, 2.0, 0.0);
             vfov = 20.0;
         }
         4 => {
-            world = light_demo();
+            let (w, l) = light_demo();
+            world = w;
+            lights = l;
             lookfrom = Point::new(13.0, 5.0, 10.0);
             lookat = Point::new(0.0, 0.0, 0.0);
             dist_to_focus = 15.0;
             aperture = 0.4;
             vfov = 40.0;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
             samples_per_pixel = 300;
         }
         5 => {
@@ -101,20 +156,20 @@ fn main() {
             lookfrom = Point::new(13.0, 2.0, 3.0);
             lookat = Point::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = Color::new(0.5, 0.8, 0.8);
+            background = Arc::new(Color::new(0.5, 0.8, 0.8));
         }
         6 => {
             world = earth();
             lookfrom = Point::new(13.0, 2.0, 3.0);
             lookat = Point::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = Color::new(0.5, 0.8, 0.8);
+            background = Arc::new(Color::new(0.5, 0.8, 0.8));
         }
         7 => {
             world = cornell_box();
             aspect_ratio = 1.0;
             samples_per_pixel = 200;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
             lookfrom = Point::new(278.0, 278.0, -800.0);
             lookat = Point::new(278.0, 278.0, 0.0);
             vfov = 40.0;
@@ -126,13 +181,13 @@ fn main() {
             lookfrom = Point::new(278.0, 278.0, -800.0);
             lookat = Point::new(278.0, 278.0, 0.0);
             vfov = 40.0;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
         }
         9 => {
             world = final_scene();
             aspect_ratio = 1.0;
             samples_per_pixel = 1000;
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
             lookfrom = Point::new(478.0, 278.0, -600.0);
             lookat = Point::new(278., 278., 0.);
             vfov = 40.0;
@@ -141,7 +196,7 @@ fn main() {
             world = try_triangle();
             aspect_ratio = 1.0;
             samples_per_pixel = 256;
-            background = Color::new(0.52, 0.80, 0.92);
+            background = Arc::new(Color::new(0.52, 0.80, 0.92));
             lookfrom = Point::new(3., 2., -7.);
             lookat = Point::new(0., 0., 0.);
             vfov = 40.0;
@@ -150,14 +205,197 @@ fn main() {
             world = kaleidoscope();
             aspect_ratio = 1.0;
             samples_per_pixel = 256;
-            background = Color::new(0.52, 0.80, 0.92);
+            background = Arc::new(Color::new(0.52, 0.80, 0.92));
             lookfrom = Point::new(1.5, -2.4, -0.866);
             vup = Vec3::new(0., 0., -1.);
             lookat = Point::new(1.5, 0., -0.866);
             vfov = 70.0;
         }
+        12 => {
+            world = smooth_surfaces();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 4.0, 9.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 35.0;
+        }
+        13 => {
+            world = hair_and_grass();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 2.0, 8.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        14 => {
+            world = particles();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 64;
+            background = Arc::new(Color::new(0.02, 0.02, 0.05));
+            lookfrom = Point::new(0.0, 6.0, 14.0);
+            lookat = Point::new(0.0, 0.0, 0.0);
+            vfov = 40.0;
+        }
+        15 => {
+            world = smoke_and_fire();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.5, 0.6, 0.8));
+            lookfrom = Point::new(0.0, 2.0, 10.0);
+            lookat = Point::new(0.0, 1.3, 0.0);
+            vfov = 35.0;
+        }
+        16 => {
+            world = nested_dielectrics();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 2.0, 7.0);
+            lookat = Point::new(0.0, 0.9, 0.0);
+            vfov = 30.0;
+        }
+        17 => {
+            world = microfacet_materials();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 2.7, 12.0);
+            lookat = Point::new(0.0, 2.7, 0.0);
+            vfov = 35.0;
+        }
+        18 => {
+            world = principled_materials();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 35.0;
+        }
+        19 => {
+            world = textured_parameters();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 30.0;
+        }
+        20 => {
+            world = bumpy_surfaces();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 30.0;
+        }
+        21 => {
+            world = cutouts();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 1.5, 8.0);
+            lookat = Point::new(0.0, 1.2, 0.0);
+            vfov = 35.0;
+        }
+        22 => {
+            world = two_sided();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.02, 0.02, 0.03));
+            lookfrom = Point::new(3.0, 3.0, 9.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+        }
+        23 => {
+            world = thin_films();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 30.0;
+        }
+        24 => {
+            world = subsurface_materials();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.05, 0.05, 0.06));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 30.0;
+        }
+        25 => {
+            world = prism();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 1024;
+            background = Arc::new(Color::new(1.0, 1.0, 1.0));
+            lookfrom = Point::new(0.0, 1.0, 7.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+            spectral = true;
+        }
+        26 => {
+            world = measured_materials();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 256;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 10.0);
+            lookat = Point::new(0.0, 0.8, 0.0);
+            vfov = 30.0;
+        }
+        27 => {
+            world = procedural_textures();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 11.0);
+            lookat = Point::new(0.0, 1.2, 0.0);
+            vfov = 32.0;
+        }
+        28 => {
+            world = texture_graph();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(Color::new(0.7, 0.8, 1.0));
+            lookfrom = Point::new(0.0, 3.0, 11.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 32.0;
+        }
+        29 => {
+            world = environment_lighting();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(EnvironmentMap::new_from_texture(afternoon_sky(), 1.0, 0.0));
+            lookfrom = Point::new(0.0, 2.0, 10.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        30 => {
+            world = environment_lighting();
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(PhysicalSky::new(15.0, -50.0, 3.0));
+            lookfrom = Point::new(0.0, 2.0, 10.0);
+            lookat = Point::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        31 => {
+            let (w, l) = analytic_lights();
+            world = w;
+            lights = l;
+            aspect_ratio = 16.0 / 9.0;
+            samples_per_pixel = 128;
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
+            lookfrom = Point::new(0.0, 2.5, 11.0);
+            lookat = Point::new(0.0, 1.2, 0.0);
+            vfov = 34.0;
+        }
         _ => {
-            background = Color::new(0.0, 0.0, 0.0);
+            background = Arc::new(Color::new(0.0, 0.0, 0.0));
         }
     };
 
@@ -188,6 +426,8 @@ fn main() {
 
     let thread_num = if is_ci() { 2 } else { 8 };
 
+    lights.build();
+    let lights = Arc::new(lights);
     let (tx, rx) = channel();
 
     for i in 0..thread_num {
@@ -197,7 +437,14 @@ fn main() {
         let _tx = tx.clone();
         let _world = world.clone();
         let _cam = cam.clone();
+        let _background = background.clone();
+        let _lights = lights.clone();
         thread::spawn(move || {
+            let _scene = Scene {
+                world: &_world,
+                background: &*_background,
+                lights: &_lights,
+            };
             for x in start..end {
                 let mut temp = ThreadTemp { x, color: vec![] };
                 for y in 0..height {
@@ -206,8 +453,13 @@ fn main() {
                         let u = (x as f64 + random_double(0.0, 1.0)) / (width - 1) as f64;
                         let v =
                             ((height - y) as f64 + random_double(0.0, 1.0)) / (height - 1) as f64;
-                        let r = _cam.get_ray(u, v);
-                        pixel_color += ray_color(&r, &background, &_world, MAX_DEPTH);
+                        let (r, diff) =
+                            _cam.get_ray(u, v, 1.0 / (width - 1) as f64, 1.0 / (height - 1) as f64);
+                        pixel_color += if spectral {
+                            ray_color_spectral(&r, &diff, &_scene, MAX_DEPTH)
+                        } else {
+                            ray_color(&r, &diff, &_scene, MAX_DEPTH)
+                        };
                     }
                     let mut r = pixel_color.x;
                     let mut g = pixel_color.y;
@@ -343,8 +595,9 @@ pub fn simple_light() -> BVHNode {
     BVHNode::new(&mut world, 0.0, 1.0)
 }
 
-pub fn light_demo() -> BVHNode {
+pub fn light_demo() -> (BVHNode, LightList) {
     let mut world = HittableList::new();
+    let mut lights = LightList::new();
     let checker = Arc::new(CheckerTexture::new(
         Color::new(0.2, 0.3, 0.1),
         Color::new(0.9, 0.9, 0.9),
@@ -379,7 +632,9 @@ pub fn light_demo() -> BVHNode {
                     let difflight = Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(
                         Color::random(0.0, 1.0),
                     ))));
-                    world.add(Arc::new(Sphere::new(center, r, difflight)))
+                    let sphere = Arc::new(Sphere::new(center, r, difflight));
+                    world.add(sphere.clone());
+                    lights.add(sphere);
                 } else if choose_mat < 0.85 {
                     // metal
                     let albedo = Color::random(0.5, 1.0);
@@ -399,11 +654,9 @@ pub fn light_demo() -> BVHNode {
         Color::new(1.0, 0.64, 0.0),
     ))));
 
-    world.add(Arc::new(Sphere::new(
-        Point::new(0.0, 0.9, 0.0),
-        0.9,
-        difflight,
-    )));
+    let sphere = Arc::new(Sphere::new(Point::new(0.0, 0.9, 0.0), 0.9, difflight));
+    world.add(sphere.clone());
+    lights.add(sphere);
 
     let material1 = Arc::new(Dielectric::new(1.5));
     let material3 = Arc::new(Metal::new(&Color::new(0.7, 0.6, 0.5), 0.0));
@@ -418,13 +671,13 @@ pub fn light_demo() -> BVHNode {
         0.65,
         material1,
     )));
-    BVHNode::new(&mut world, 0.0, 0.1)
+    (BVHNode::new(&mut world, 0.0, 0.1), lights)
 }
 
 fn two_perlin_spheres() -> BVHNode {
     let mut world = HittableList::new();
 
-    let pertext = Arc::new(NoiseTexture::new(4.0));
+    let pertext = Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 4.0));
     world.add(Arc::new(Sphere::new(
         Point::new(0.0, -1000.0, 0.0),
         1000.0,
@@ -442,7 +695,11 @@ fn two_perlin_spheres() -> BVHNode {
 }
 
 fn earth() -> BVHNode {
-    let earth_texture = Arc::new(ImageTexture::new("image_texture/earthmap.jpg"));
+    // repeat so that filtering blends across the seam at u = 0
+    let earth_texture = Arc::new(ImageTexture::new_with_wrap(
+        "image_texture/earthmap.jpg",
+        WrapMode::Repeat,
+    ));
     let earth_surface = Arc::new(Lambertian {
         albedo: earth_texture,
     });
@@ -639,16 +896,15 @@ fn final_scene() -> BVHNode {
         Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.9), 10.0)),
     )));
 
-    let boundary = Arc::new(Sphere::new(
+    // blue subsurface ball, density 0.02 split by an albedo of (0.2, 0.4, 0.9)
+    let blue_fog = Arc::new(HomogeneousMedium::new(
+        Color::new(0.016, 0.012, 0.002),
+        Color::new(0.004, 0.008, 0.018),
+    ));
+    world.add(Arc::new(Sphere::new(
         Point::new(360.0, 150.0, 145.0),
         70.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-    world.add(boundary.clone());
-    world.add(Arc::new(ConstantMedium::new_from_color(
-        boundary,
-        0.02,
-        Color::new(0.2, 0.4, 0.9),
+        Arc::new(Dielectric::new_with_medium(1.5, blue_fog)),
     )));
     let boundary1 = Arc::new(Sphere::new(
         Point::new(0.0, 0.0, 0.0),
@@ -669,29 +925,20 @@ fn final_scene() -> BVHNode {
         100.0,
         emat,
     )));
-    let pertext = Arc::new(NoiseTexture::new(0.1));
+    let pertext = Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 0.1));
     world.add(Arc::new(Sphere::new(
         Point::new(220.0, 280.0, 300.0),
         80.0,
         Arc::new(Lambertian { albedo: pertext }),
     )));
 
-    let mut boxes2 = HittableList::new();
     let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
     let ns = 100;
-    for _j in 0..ns {
-        boxes2.add(Arc::new(Sphere::new(
-            Point::random(0.0, 165.0),
-            10.0,
-            white.clone(),
-        )));
-    }
+    let centers = (0..ns).map(|_j| Point::random(0.0, 165.0)).collect();
+    let boxes2 = SphereSet::new(centers, vec![10.0; ns], white);
 
     world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(BVHNode::new(&mut boxes2, 0.0, 1.0)),
-            15.0,
-        )),
+        Arc::new(RotateY::new(Arc::new(boxes2), 15.0)),
         &Vec3::new(-100.0, 270.0, 395.0),
     )));
 
@@ -763,3 +1010,1106 @@ fn kaleidoscope() -> BVHNode {
     )));
     BVHNode::new(&mut world, 0., 1.)
 }
+
+fn smooth_surfaces() -> BVHNode {
+    let mut world = HittableList::new();
+
+    // a wavy bicubic sheet as the ground
+    let mut control = [[Point::zero(); 4]; 4];
+    for (i, row) in control.iter_mut().enumerate() {
+        for (j, c) in row.iter_mut().enumerate() {
+            let height = if (i + j) % 2 == 0 { -0.6 } else { 0.6 };
+            *c = Point::new(
+                -8.0 + 16.0 * j as f64 / 3.0,
+                height,
+                -8.0 + 16.0 * i as f64 / 3.0,
+            );
+        }
+    }
+    let checker = Arc::new(CheckerTexture::new(
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(BezierPatch::new(
+        control,
+        Arc::new(Lambertian { albedo: checker }),
+    )));
+
+    // the same cube before and after Catmull-Clark subdivision
+    let cube = QuadMesh::new_box(&Point::new(-1.0, 0.6, -1.0), &Point::new(1.0, 2.6, 1.0));
+    let mut rough = cube.to_triangles(Arc::new(Lambertian::new(Color::new(0.8, 0.3, 0.2))));
+    world.add(Arc::new(Translate::new(
+        Arc::new(BVHNode::new(&mut rough, 0.0, 1.0)),
+        &Vec3::new(-2.5, 0.0, 0.0),
+    )));
+    let mut smooth = cube
+        .subdivide_levels(4)
+        .to_triangles(Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.9), 0.05)));
+    world.add(Arc::new(Translate::new(
+        Arc::new(BVHNode::new(&mut smooth, 0.0, 1.0)),
+        &Vec3::new(2.5, 0.0, 0.0),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn hair_and_grass() -> BVHNode {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.3, 0.25, 0.15))),
+    )));
+
+    // grass blades as ribbons facing random directions
+    let grass = Arc::new(Lambertian::new(Color::new(0.2, 0.5, 0.1)));
+    let mut blades = HittableList::new();
+    for _i in 0..2000 {
+        let root = Point::new(random_double(-5.0, 5.0), 0.0, random_double(-4.0, 2.0));
+        let height = random_double(0.3, 0.8);
+        let lean = Vec3::new(random_double(-0.3, 0.3), 0.0, random_double(-0.3, 0.3));
+        let cp = [
+            root,
+            root + Vec3::new(0.0, height / 3.0, 0.0),
+            root + Vec3::new(0.0, height * 2.0 / 3.0, 0.0) + lean * 0.5,
+            root + Vec3::new(0.0, height, 0.0) + lean,
+        ];
+        let facing = random_double(0.0, 2.0 * PI);
+        let normal = Vec3::new(facing.cos(), 0.0, facing.sin());
+        for seg in Curve::new_segments(
+            cp,
+            0.04,
+            0.0,
+            CurveType::Ribbon(normal, normal),
+            2,
+            grass.clone(),
+        )
+        .objects
+        {
+            blades.add(seg);
+        }
+    }
+    world.add(Arc::new(BVHNode::new(&mut blades, 0.0, 1.0)));
+
+    // a furry ball
+    let center = Point::new(0.0, 1.0, 0.0);
+    let hair = Arc::new(Hair::new_from_melanin(1.3, 0.2, 0.3, 0.3));
+    let mut strands = HittableList::new();
+    for _i in 0..3000 {
+        let dir = random_unit_vector();
+        let root = center + dir * 0.8;
+        let side = Vec3::cross(dir, Vec3::new(0.0, 1.0, 0.0)) * 0.15;
+        let cp = [
+            root,
+            root + dir * 0.15,
+            root + dir * 0.3 - side + Vec3::new(0.0, -0.1, 0.0),
+            root + dir * 0.4 - side * 2.0 + Vec3::new(0.0, -0.25, 0.0),
+        ];
+        for seg in Curve::new_segments(cp, 0.01, 0.004, CurveType::Tube, 4, hair.clone()).objects {
+            strands.add(seg);
+        }
+    }
+    strands.add(Arc::new(Sphere::new(
+        center,
+        0.8,
+        Arc::new(Lambertian::new(Color::new(0.1, 0.07, 0.05))),
+    )));
+    world.add(Arc::new(BVHNode::new(&mut strands, 0.0, 1.0)));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn particles() -> BVHNode {
+    let mut world = HittableList::new();
+
+    // a spiral galaxy of colored particles
+    let n = 200_000;
+    let mut centers = Vec::with_capacity(n);
+    let mut radii = Vec::with_capacity(n);
+    let mut colors = Vec::with_capacity(n);
+    for i in 0..n {
+        let arm = (i % 3) as f64 * 2.0 * PI / 3.0;
+        let dist = random_double(0.2, 1.0).powi(2) * 6.0;
+        let angle = arm + dist * 0.8 + random_double(-0.3, 0.3);
+        let height = random_double(-0.15, 0.15) * (1.0 - dist / 6.0);
+        centers.push(Point::new(dist * angle.cos(), height, dist * angle.sin()));
+        radii.push(random_double(0.005, 0.02));
+        let heat = 1.0 - dist / 6.0;
+        colors.push(Color::new(
+            0.5 + 0.5 * heat,
+            0.4 + 0.4 * heat,
+            1.0 - 0.6 * heat,
+        ));
+    }
+    let glow = Arc::new(DiffuseLight::new_from_color(Color::new(3.0, 3.0, 3.0)));
+    world.add(Arc::new(SphereSet::new_with_colors(
+        centers, radii, colors, glow,
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.5, 0.0),
+        1000.0,
+        Arc::new(Metal::new(&Color::new(0.3, 0.3, 0.35), 0.05)),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn smoke_and_fire() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+
+    // a cloud bank of turbulence
+    let cloud_box = Arc::new(Box6::new(
+        &Point::new(-4.0, 1.5, -3.0),
+        &Point::new(4.0, 3.5, -1.0),
+        Arc::new(Lambertian::new(Color::zero())),
+    ));
+    // mostly forward with a little back scattering, like water droplets
+    world.add(Arc::new(HeterogeneousMedium::new_with_phase(
+        cloud_box,
+        Arc::new(PerlinDensity::new(0.8, 5)),
+        Color::new(0.05, 0.05, 0.05),
+        Color::new(3.0, 3.0, 3.0),
+        Arc::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.9)),
+    )));
+
+    // a glowing ball of smoke, denser in the middle and reddened by colored scattering
+    let center = Point::new(0.0, 1.0, 1.5);
+    let radius = 1.0;
+    let ball = Arc::new(Sphere::new(
+        center,
+        radius,
+        Arc::new(Lambertian::new(Color::zero())),
+    ));
+    let falloff = FnDensity::new(
+        move |p: &Point| clamp(1.0 - (*p - center).length() / radius, 0.0, 1.0),
+        1.0,
+    );
+    world.add(Arc::new(HeterogeneousMedium::new_with_emission(
+        ball,
+        Arc::new(falloff),
+        Color::new(2.0, 2.0, 2.0),
+        Color::new(3.0, 1.5, 0.8),
+        Arc::new(SolidColor::new(Color::new(3.0, 1.2, 0.3))),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn nested_dielectrics() -> BVHNode {
+    let mut world = HittableList::new();
+
+    let checker = Arc::new(CheckerTexture::new(
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian { albedo: checker }),
+    )));
+
+    // a glass bowl of tinted water with air bubbles. The water pokes into the glass wall at
+    // the bottom, the glass has the higher priority so it wins there.
+    world.add(Arc::new(Sphere::new(
+        Point::new(-1.2, 1.0, 0.0),
+        1.0,
+        Arc::new(Dielectric::new_nested(1.5, 2, None)),
+    )));
+    let water = Arc::new(HomogeneousMedium::new(
+        Color::new(0.4, 0.1, 0.02),
+        Color::zero(),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(-1.2, 0.95, 0.0),
+        0.96,
+        Arc::new(Dielectric::new_nested(1.33, 1, Some(water))),
+    )));
+    for center in [
+        Point::new(-1.4, 0.7, 0.2),
+        Point::new(-0.9, 1.1, 0.3),
+        Point::new(-1.3, 1.3, 0.4),
+    ]
+    .iter()
+    {
+        world.add(Arc::new(Sphere::new(
+            *center,
+            0.15,
+            Arc::new(Dielectric::new_nested(1.0, 3, None)),
+        )));
+    }
+
+    // plain glass next to it for comparison
+    world.add(Arc::new(Sphere::new(
+        Point::new(1.2, 1.0, 0.0),
+        1.0,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn microfacet_materials() -> BVHNode {
+    let mut world = HittableList::new();
+
+    let checker = Arc::new(CheckerTexture::new(
+        Color::new(0.2, 0.2, 0.2),
+        Color::new(0.8, 0.8, 0.8),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian { albedo: checker }),
+    )));
+
+    // rows of frosted glass, aluminium, copper and gold, rougher to the right
+    for row in 0..4 {
+        for i in 0..4 {
+            let roughness = i as f64 / 3.0;
+            let mat: Arc<dyn Material> = match row {
+                0 => Arc::new(RoughDielectric::new(1.5, roughness)),
+                1 => Arc::new(Conductor::aluminium(roughness)),
+                2 => Arc::new(Conductor::copper(roughness)),
+                _ => Arc::new(Conductor::gold(roughness)),
+            };
+            world.add(Arc::new(Sphere::new(
+                Point::new(-2.1 + 1.4 * i as f64, 0.6 + 1.4 * row as f64, 0.0),
+                0.6,
+                mat,
+            )));
+        }
+    }
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn principled_materials() -> BVHNode {
+    let mut world = HittableList::new();
+
+    let mut ground = Principled::new(Arc::new(CheckerTexture::new(
+        Color::new(0.2, 0.2, 0.2),
+        Color::new(0.8, 0.8, 0.8),
+    )));
+    ground.roughness = scalar(0.9);
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(ground),
+    )));
+
+    let red = Color::new(0.8, 0.1, 0.1);
+    let mut coated_plastic = Principled::new_from_color(red);
+    coated_plastic.clearcoat = scalar(1.0);
+    let mut brushed_metal = Principled::new_from_color(Color::new(0.9, 0.7, 0.4));
+    brushed_metal.metallic = scalar(1.0);
+    brushed_metal.roughness = scalar(0.4);
+    let mut frosted_glass = Principled::new_from_color(Color::new(0.9, 1.0, 0.95));
+    frosted_glass.transmission = scalar(1.0);
+    frosted_glass.roughness = scalar(0.2);
+    let mut velvet = Principled::new_from_color(Color::new(0.2, 0.05, 0.4));
+    velvet.roughness = scalar(1.0);
+    velvet.sheen = scalar(1.0);
+    let mut skin = Principled::new_from_color(Color::new(0.9, 0.6, 0.5));
+    skin.subsurface = scalar(1.0);
+    skin.roughness = scalar(0.6);
+    // roughness driven by a texture: polished where the noise is dark
+    let mut mottled = Principled::new_from_color(Color::new(0.1, 0.3, 0.8));
+    mottled.roughness = Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 4.0));
+    mottled.metallic = scalar(0.5);
+
+    let materials: Vec<Principled> = vec![
+        coated_plastic,
+        brushed_metal,
+        frosted_glass,
+        velvet,
+        skin,
+        mottled,
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Point::new(-3.75 + 1.5 * i as f64, 0.65, 0.0),
+            0.65,
+            Arc::new(mat),
+        )));
+    }
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn textured_parameters() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+
+    // rusted steel: rust patches are brown and rough
+    let rust = Arc::new(Metal::new_from_texture(
+        Arc::new(CheckerTexture::new(
+            Color::new(0.45, 0.2, 0.1),
+            Color::new(0.8, 0.8, 0.85),
+        )),
+        Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 6.0)),
+    ));
+    world.add(Arc::new(Sphere::new(Point::new(-3.0, 0.8, 0.0), 0.8, rust)));
+
+    // glass with frosted patches
+    let frosted = Arc::new(RoughDielectric::new_from_texture(
+        scalar(1.5),
+        Arc::new(CheckerTexture::new(
+            Color::zero(),
+            Color::new(0.6, 0.6, 0.6),
+        )),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(-1.0, 0.8, 0.0),
+        0.8,
+        frosted,
+    )));
+
+    // glass whose IOR changes between patches
+    let mixed_glass = Arc::new(Dielectric::new_from_texture(Arc::new(CheckerTexture::new(
+        Color::new(1.2, 1.2, 1.2),
+        Color::new(2.0, 2.0, 2.0),
+    ))));
+    world.add(Arc::new(Sphere::new(
+        Point::new(1.0, 0.8, 0.0),
+        0.8,
+        mixed_glass,
+    )));
+
+    // worn copper
+    let copper = Arc::new(Conductor::new_from_texture(
+        Arc::new(SolidColor::new(Color::new(0.200, 0.924, 1.102))),
+        Arc::new(SolidColor::new(Color::new(3.912, 2.452, 2.142))),
+        Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 3.0)),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(3.0, 0.8, 0.0),
+        0.8,
+        copper,
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn bumpy_surfaces() -> BVHNode {
+    let mut world = HittableList::new();
+
+    // tiles with noisy bumps, the rect provides the tangents
+    world.add(Arc::new(XZRect::new(
+        -20.0,
+        20.0,
+        -20.0,
+        20.0,
+        0.0,
+        Arc::new(BumpMap::new(
+            Arc::new(Lambertian {
+                albedo: Arc::new(CheckerTexture::new(
+                    Color::new(0.2, 0.3, 0.1),
+                    Color::new(0.9, 0.9, 0.9),
+                )),
+            }),
+            Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 2.0)),
+            0.02,
+        )),
+    )));
+
+    // relief from the brightness of the earth map
+    let earth_texture: Arc<dyn Texture> = Arc::new(ImageTexture::new("image_texture/earthmap.jpg"));
+    let earth = Arc::new(BumpMap::new(
+        Arc::new(Lambertian {
+            albedo: earth_texture.clone(),
+        }),
+        earth_texture,
+        0.002,
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(-2.0, 1.0, 0.0),
+        1.0,
+        earth,
+    )));
+
+    // hammered metal and rippled glass
+    let hammered = Arc::new(BumpMap::new(
+        Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.85), 0.05)),
+        Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 8.0)),
+        0.005,
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, 1.0, 0.0),
+        1.0,
+        hammered,
+    )));
+    let rippled = Arc::new(BumpMap::new(
+        Arc::new(Dielectric::new(1.5)),
+        Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 4.0)),
+        0.005,
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(2.0, 1.0, 0.0),
+        1.0,
+        rippled,
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn cutouts() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.4, 0.5, 0.3))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(1.5, 0.8, -2.5),
+        0.8,
+        Arc::new(Lambertian::new(Color::new(0.7, 0.2, 0.1))),
+    )));
+
+    // chain-link fence: diagonal wires on a transparent background
+    let fence = Arc::new(ImageTexture::new_from_image(
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(512, 128, |x, y| {
+            let wire = (x + y) % 16 < 2 || (x + 16 - y % 16) % 16 < 2;
+            image::Rgba([160, 160, 170, if wire { 255 } else { 0 }])
+        })),
+        ColorSpace::Srgb,
+    ));
+    world.add(Arc::new(AlphaMask::new(
+        Arc::new(XYRect::new(
+            -4.0,
+            4.0,
+            0.0,
+            2.0,
+            -1.0,
+            Arc::new(Lambertian {
+                albedo: fence.clone(),
+            }),
+        )),
+        fence,
+    )));
+
+    // leaves with soft edges around a branch
+    let leaf = Arc::new(ImageTexture::new_from_image(
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 32, |x, y| {
+            let dx = (x as f64 - 31.5) / 32.0;
+            let dy = (y as f64 - 15.5) / 16.0;
+            let edge = clamp((1.0 - (dx * dx + dy * dy).sqrt()) * 8.0, 0.0, 1.0);
+            let vein = if y == 15 || y == 16 { 40 } else { 0 };
+            image::Rgba([50 + vein, 120 + vein, 30, (edge * 255.0) as u8])
+        })),
+        ColorSpace::Srgb,
+    ));
+    let leaf_mat = Arc::new(Lambertian {
+        albedo: leaf.clone(),
+    });
+    for _i in 0..40 {
+        let quad = Arc::new(AlphaMask::new(
+            Arc::new(XYRect::new(-0.3, 0.3, -0.15, 0.15, 0.0, leaf_mat.clone())),
+            leaf.clone(),
+        ));
+        world.add(Arc::new(Translate::new(
+            Arc::new(RotateY::new(quad, random_double(0.0, 360.0))),
+            &(Point::new(-1.5, 1.6, 0.5) + Vec3::random(-0.6, 0.6)),
+        )));
+    }
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn two_sided() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+
+    // panel over the scene that lights downwards only, its top is plain grey
+    world.add(Arc::new(FlipFace::new(Arc::new(XZRect::new(
+        -1.5,
+        1.5,
+        -1.5,
+        1.5,
+        4.0,
+        Arc::new(TwoSided::new(
+            Arc::new(DiffuseLight::new_from_color(Color::new(6.0, 6.0, 6.0))),
+            Arc::new(Lambertian::new(Color::new(0.3, 0.3, 0.3))),
+        )),
+    )))));
+
+    // a box with a different color on every side
+    let side = |r: f64, g: f64, b: f64| -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(r, g, b)))
+    };
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Box6::new_with_sides(
+                &Point::new(-0.6, 0.0, -0.6),
+                &Point::new(0.6, 1.2, 0.6),
+                [
+                    side(0.8, 0.2, 0.2),
+                    side(0.2, 0.8, 0.2),
+                    side(0.9, 0.9, 0.9),
+                    side(0.1, 0.1, 0.1),
+                    side(0.2, 0.2, 0.8),
+                    side(0.8, 0.8, 0.2),
+                ],
+            )),
+            30.0,
+        )),
+        &Vec3::new(-1.5, 0.0, 0.0),
+    )));
+
+    // rounded cube keeping the material of each face of the original box
+    let cube = QuadMesh::new_box(&Point::new(0.9, 0.0, -0.6), &Point::new(2.1, 1.2, 0.6))
+        .subdivide_levels(3);
+    let brass: Arc<dyn Material> = Arc::new(Metal::new(&Color::new(0.8, 0.6, 0.2), 0.1));
+    let mats = vec![
+        brass.clone(),
+        brass,
+        side(0.9, 0.9, 0.9),
+        side(0.2, 0.3, 0.7),
+        side(0.7, 0.2, 0.5),
+        side(0.7, 0.2, 0.5),
+    ];
+    world.add(Arc::new(BVHNode::new(
+        &mut cube.to_triangles_with(&mats),
+        0.0,
+        1.0,
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn thin_films() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.08, 0.08, 0.08))),
+    )));
+
+    // puddle of water with an oil slick on it
+    world.add(Arc::new(Box6::new(
+        &Point::new(-4.0, 0.0, -1.0),
+        &Point::new(4.0, 0.02, 3.0),
+        Arc::new(Dielectric::new_with_film(
+            1.33,
+            ThinFilm::new_from_texture(
+                Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 1.5)),
+                1.47,
+            ),
+        )),
+    )));
+
+    // soap bubble: a film of water with air on both sides
+    world.add(Arc::new(Sphere::new(
+        Point::new(-2.0, 1.0, 0.0),
+        0.9,
+        Arc::new(Dielectric::new_with_film(
+            1.0,
+            ThinFilm::new_from_texture(
+                Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 2.0)),
+                1.33,
+            ),
+        )),
+    )));
+
+    // titanium, blue from its anodized oxide layer
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, 1.0, 0.0),
+        0.9,
+        Arc::new(Metal::new_with_film(
+            &Color::new(0.55, 0.55, 0.55),
+            0.05,
+            ThinFilm::new(0.06, 2.4),
+        )),
+    )));
+    // glass with a quarter wave anti-reflection coating
+    world.add(Arc::new(Sphere::new(
+        Point::new(2.0, 1.0, 0.0),
+        0.9,
+        Arc::new(Dielectric::new_with_film(
+            1.5,
+            ThinFilm::new(0.55 / (4.0 * 1.38), 1.38),
+        )),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn subsurface_materials() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(Color::new(0.4, 0.4, 0.4))),
+    )));
+    // light from behind shows how far light gets through
+    world.add(Arc::new(XYRect::new(
+        -4.0,
+        4.0,
+        2.5,
+        5.0,
+        -3.0,
+        Arc::new(DiffuseLight::new_from_color(Color::new(4.0, 4.0, 4.0))),
+    )));
+    world.add(Arc::new(FlipFace::new(Arc::new(XZRect::new(
+        -2.0,
+        2.0,
+        -1.0,
+        2.0,
+        6.0,
+        Arc::new(DiffuseLight::new_from_color(Color::new(2.0, 2.0, 2.0))),
+    )))));
+
+    // skin, light goes furthest in red
+    world.add(Arc::new(Sphere::new(
+        Point::new(-3.0, 0.8, 0.0),
+        0.8,
+        Arc::new(Subsurface::new_from_color(
+            Color::new(0.85, 0.55, 0.45),
+            Color::new(0.25, 0.1, 0.06),
+            1.4,
+        )),
+    )));
+
+    // veined marble
+    world.add(Arc::new(Sphere::new(
+        Point::new(-1.0, 0.8, 0.0),
+        0.8,
+        Arc::new(Subsurface::new(
+            Arc::new(MarbleTexture::new(Color::ones(), Color::zero(), 3.0)),
+            Color::new(0.15, 0.15, 0.15),
+            1.5,
+        )),
+    )));
+
+    // wax and jade
+    world.add(Arc::new(Sphere::new(
+        Point::new(1.0, 0.8, 0.0),
+        0.8,
+        Arc::new(Subsurface::new_from_color(
+            Color::new(0.95, 0.85, 0.6),
+            Color::new(0.3, 0.25, 0.15),
+            1.45,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(3.0, 0.8, 0.0),
+        0.8,
+        Arc::new(Subsurface::new_from_color(
+            Color::new(0.3, 0.8, 0.5),
+            Color::new(0.3, 0.5, 0.4),
+            1.6,
+        )),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn prism() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(XZRect::new(
+        -20.0,
+        20.0,
+        -20.0,
+        20.0,
+        0.0,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    // black and white checks behind and above, seen through glass their edges turn into
+    // rainbows
+    let checker: Arc<dyn Material> = Arc::new(Lambertian {
+        albedo: Arc::new(CheckerTexture::new(Color::zero(), Color::ones())),
+    });
+    world.add(Arc::new(XYRect::new(
+        -20.0,
+        20.0,
+        0.0,
+        5.0,
+        -4.0,
+        checker.clone(),
+    )));
+    world.add(Arc::new(XZRect::new(-20.0, 20.0, -20.0, 2.0, 5.0, checker)));
+
+    // equilateral prism lying along x, apex down, counter-clockwise faces seen from outside
+    let glass: Arc<dyn Material> = Arc::new(Dielectric::new_with_dispersion(Dispersion::sf10()));
+    let corners = [(0.2, 0.0), (1.7, 0.866), (1.7, -0.866)];
+    let p = |x: f64, c: usize| Point::new(x, corners[c].0, corners[c].1);
+    let mut add_triangle = |a: Point, b: Point, c: Point| {
+        world.add(Arc::new(Triangle::new(b, c, a, glass.clone())));
+    };
+    add_triangle(p(1.5, 0), p(1.5, 2), p(1.5, 1));
+    add_triangle(p(-1.5, 0), p(-1.5, 1), p(-1.5, 2));
+    for k in 0..3 {
+        let l = (k + 1) % 3;
+        add_triangle(p(-1.5, k), p(1.5, k), p(1.5, l));
+        add_triangle(p(-1.5, k), p(1.5, l), p(-1.5, l));
+    }
+
+    // a ball of dense flint next to one of crown glass on the floor
+    world.add(Arc::new(Sphere::new(
+        Point::new(-2.5, 0.6, 1.0),
+        0.6,
+        Arc::new(Dielectric::new_with_dispersion(Dispersion::sf10())),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(2.5, 0.6, 1.0),
+        0.6,
+        Arc::new(Dielectric::new_with_dispersion(Dispersion::bk7())),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+// needs BRDFs from the MERL database in brdfs/, next to analytic materials for comparison
+fn measured_materials() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian {
+            albedo: Arc::new(CheckerTexture::new(
+                Color::new(0.2, 0.2, 0.2),
+                Color::new(0.8, 0.8, 0.8),
+            )),
+        }),
+    )));
+
+    let measured: Vec<Arc<dyn Material>> = vec![
+        Arc::new(MeasuredBrdf::load("brdfs/blue-metallic-paint.binary")),
+        Arc::new(MeasuredBrdf::load("brdfs/gold-metallic-paint.binary")),
+        Arc::new(MeasuredBrdf::load("brdfs/red-fabric.binary")),
+    ];
+    let analytic: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Principled {
+            metallic: scalar(0.5),
+            roughness: scalar(0.3),
+            clearcoat: scalar(1.0),
+            ..Principled::new_from_color(Color::new(0.1, 0.2, 0.6))
+        }),
+        Arc::new(Conductor::gold(0.3)),
+        Arc::new(Lambertian::new(Color::new(0.5, 0.1, 0.1))),
+    ];
+    for (i, (m, a)) in measured.into_iter().zip(analytic).enumerate() {
+        let x = -3.0 + 2.0 * i as f64;
+        world.add(Arc::new(Sphere::new(Point::new(x, 0.6, 0.9), 0.6, m)));
+        world.add(Arc::new(Sphere::new(
+            Point::new(x + 0.6, 0.6, -0.9),
+            0.6,
+            a,
+        )));
+    }
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn procedural_textures() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(XZRect::new(
+        -8.0,
+        8.0,
+        -4.0,
+        6.0,
+        0.0,
+        Arc::new(Lambertian {
+            albedo: Arc::new(UvCheckerTexture::new(
+                Color::new(0.2, 0.2, 0.2),
+                Color::new(0.8, 0.8, 0.8),
+                16.0,
+                10.0,
+            )),
+        }),
+    )));
+    world.add(Arc::new(XYRect::new(
+        -8.0,
+        8.0,
+        0.0,
+        5.0,
+        -2.0,
+        Arc::new(Lambertian {
+            albedo: Arc::new(BrickTexture::new(
+                Color::new(0.55, 0.2, 0.12),
+                Color::new(0.7, 0.68, 0.65),
+                1.0 / 32.0,
+                1.0 / 40.0,
+                0.004,
+            )),
+        }),
+    )));
+
+    let textures: Vec<Arc<dyn Texture>> = vec![
+        Arc::new(MarbleTexture::new(
+            Color::new(0.9, 0.9, 0.88),
+            Color::new(0.2, 0.25, 0.3),
+            3.0,
+        )),
+        Arc::new(WoodTexture::new(
+            Color::new(0.75, 0.55, 0.3),
+            Color::new(0.35, 0.2, 0.08),
+            8.0,
+        )),
+        Arc::new(WorleyTexture::new(4.0, CellularFeature::Edges)),
+        Arc::new(RidgedTexture::new(3.0, 6)),
+        Arc::new(FbmTexture::new(4.0, 6)),
+        Arc::new(StripeTexture::new(
+            Color::new(0.8, 0.1, 0.1),
+            Color::new(0.9, 0.9, 0.9),
+            Vec3::new(1.0, 1.0, 0.0),
+            4.0,
+        )),
+        Arc::new(GradientTexture::new(
+            Color::new(0.1, 0.2, 0.7),
+            Color::new(0.9, 0.7, 0.1),
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(0.0, 1.2, 0.0),
+        )),
+        Arc::new(WorleyTexture::new(3.0, CellularFeature::F1)),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        let x = -3.75 + 2.5 * (i % 4) as f64;
+        let z = if i < 4 { 1.5 } else { -0.5 };
+        let center = Point::new(x + if i < 4 { 0.0 } else { 1.25 }, 0.6, z);
+        world.add(Arc::new(Sphere::new(
+            center,
+            0.6,
+            Arc::new(Lambertian { albedo: texture }),
+        )));
+    }
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+fn texture_graph() -> BVHNode {
+    let mut world = HittableList::new();
+
+    // paving with dark wet patches where low frequency noise is high
+    let paving: Arc<dyn Texture> = Arc::new(UvTransformTexture::new(
+        Arc::new(UvCheckerTexture::new(
+            Color::new(0.55, 0.5, 0.45),
+            Color::new(0.7, 0.66, 0.6),
+            1.0,
+            1.0,
+        )),
+        (16.0, 10.0),
+        30.0,
+        (0.0, 0.0),
+    ));
+    let wet = Arc::new(MultiplyTexture::new(
+        paving.clone(),
+        Arc::new(SolidColor::new(Color::new(0.3, 0.3, 0.35))),
+    ));
+    let puddles = Arc::new(RemapTexture::new(
+        Arc::new(FbmTexture::new(0.6, 4)),
+        0.55,
+        0.6,
+        0.0,
+        1.0,
+    ));
+    world.add(Arc::new(XZRect::new(
+        -8.0,
+        8.0,
+        -4.0,
+        6.0,
+        0.0,
+        Arc::new(Lambertian {
+            albedo: Arc::new(MixTexture::new(paving, wet, puddles)),
+        }),
+    )));
+
+    // lava: ridges glowing through a dark crust
+    let lava = Arc::new(ColorRampTexture::new(
+        Arc::new(RidgedTexture::new(2.0, 6)),
+        vec![
+            (0.35, Color::new(0.05, 0.03, 0.03)),
+            (0.6, Color::new(0.7, 0.1, 0.0)),
+            (0.8, Color::new(1.0, 0.5, 0.0)),
+            (0.95, Color::new(1.0, 0.9, 0.4)),
+        ],
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(-3.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambertian { albedo: lava }),
+    )));
+
+    // an image on a box, which has no texture coordinates of its own across faces
+    let mut earth = ImageTexture::new("image_texture/earthmap.jpg");
+    earth.wrap = WrapMode::Repeat;
+    world.add(Arc::new(Box6::new(
+        &Point::new(-0.9, 0.0, -0.9),
+        &Point::new(0.9, 1.8, 0.9),
+        Arc::new(Lambertian {
+            albedo: Arc::new(TriplanarTexture::new(Arc::new(earth), 0.4)),
+        }),
+    )));
+
+    // cells with glowing gaps
+    let cells = Arc::new(AddTexture::new(
+        Arc::new(MultiplyTexture::new(
+            Arc::new(WorleyTexture::new(4.0, CellularFeature::F1)),
+            Arc::new(SolidColor::new(Color::new(0.2, 0.5, 0.3))),
+        )),
+        Arc::new(MultiplyTexture::new(
+            Arc::new(InvertTexture::new(Arc::new(RemapTexture::new(
+                Arc::new(WorleyTexture::new(4.0, CellularFeature::Edges)),
+                0.0,
+                0.08,
+                0.0,
+                1.0,
+            )))),
+            Arc::new(SolidColor::new(Color::new(0.9, 0.8, 0.2))),
+        )),
+    ));
+    world.add(Arc::new(Sphere::new(
+        Point::new(3.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambertian { albedo: cells }),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+// An equirectangular HDR sky made up in code, standing in for a photographed one: blue
+// above, haze at the horizon, a dim brown ground and a small sun about a thousand times
+// brighter than the sky. Works the same with EnvironmentMap::new("sky.hdr", ...).
+fn afternoon_sky() -> ImageTexture {
+    let (width, height) = (512, 256);
+    let sun = Vec3::new(-0.6, 0.55, -0.6).unit();
+    let sun_cos = degrees_to_radians(1.5).cos();
+    let mut texels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        // rows from the top
+        let elevation = PI * (0.5 - (y as f64 + 0.5) / height as f64);
+        for x in 0..width {
+            let phi = 2.0 * PI * ((x as f64 + 0.5) / width as f64 - 0.5);
+            let dir = Vec3::new(
+                phi.sin() * elevation.cos(),
+                elevation.sin(),
+                -phi.cos() * elevation.cos(),
+            );
+            let c = if dir * sun > sun_cos {
+                Color::new(1.0, 0.9, 0.75) * 1000.0
+            } else if dir.y > 0.0 {
+                let t = dir.y.powf(0.4);
+                Color::new(0.9, 0.9, 0.85) * (1.0 - t) + Color::new(0.25, 0.45, 0.9) * t
+            } else {
+                Color::new(0.25, 0.2, 0.15)
+            };
+            texels.push([c.x as f32, c.y as f32, c.z as f32, 1.0]);
+        }
+    }
+    ImageTexture::new_from_texels(width, height, texels)
+}
+
+fn environment_lighting() -> BVHNode {
+    let mut world = HittableList::new();
+
+    world.add(Arc::new(XZRect::new(
+        -30.0,
+        30.0,
+        -30.0,
+        30.0,
+        0.0,
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(-2.2, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambertian::new(Color::new(0.8, 0.3, 0.2))),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.0, 1.0, 0.0),
+        1.0,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point::new(2.2, 1.0, 0.0),
+        1.0,
+        Arc::new(Metal::new(&Color::new(0.9, 0.85, 0.8), 0.2)),
+    )));
+
+    BVHNode::new(&mut world, 0.0, 1.0)
+}
+
+// Lights without geometry, and a textured area light: a spot on the left sphere, downlights
+// with a photometric profile washing the back wall, cold moonlight from a directional light
+// and a screen showing the earth
+fn analytic_lights() -> (BVHNode, LightList) {
+    let mut world = HittableList::new();
+    let mut lights = LightList::new();
+
+    let grey = Arc::new(Lambertian::new(Color::new(0.6, 0.6, 0.6)));
+    world.add(Arc::new(XZRect::new(
+        -10.0,
+        10.0,
+        -3.0,
+        10.0,
+        0.0,
+        grey.clone(),
+    )));
+    world.add(Arc::new(XYRect::new(-10.0, 10.0, 0.0, 6.0, -3.0, grey)));
+
+    world.add(Arc::new(Sphere::new(
+        Point::new(-2.2, 1.0, 0.0),
+        1.0,
+        Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))),
+    )));
+    let mut glossy = Principled::new_from_color(Color::new(0.8, 0.2, 0.1));
+    glossy.roughness = Arc::new(SolidColor::new(Color::ones() * 0.3));
+    world.add(Arc::new(Sphere::new(
+        Point::new(0.6, 0.8, 0.8),
+        0.8,
+        Arc::new(glossy),
+    )));
+
+    lights.add(Arc::new(SpotLight::new(
+        Point::new(-1.0, 6.0, 3.0),
+        Point::new(-2.2, 0.0, 0.0),
+        Color::new(40.0, 36.0, 30.0),
+        18.0,
+        12.0,
+    )));
+    let profile = Arc::new(IesProfile::load("ies/ring_downlight.ies"));
+    for x in [-4.0, 0.0, 4.0].iter() {
+        lights.add(Arc::new(PointLight::new_with_profile(
+            Point::new(*x, 5.5, -2.5),
+            Color::new(6.0, 5.0, 3.5),
+            profile.clone(),
+            Vec3::new(0.0, -1.0, 0.0),
+        )));
+    }
+    lights.add(Arc::new(DirectionalLight::new(
+        Vec3::new(1.0, -1.0, -0.5),
+        Color::new(0.03, 0.04, 0.08),
+    )));
+
+    let screen = Arc::new(AreaLight::new(
+        Point::new(2.5, 0.3, -1.5),
+        Vec3::new(1.6, 0.0, 1.2),
+        Vec3::new(0.0, 1.5, 0.0),
+        Arc::new(ImageTexture::new("image_texture/earthmap.jpg")),
+    ));
+    world.add(screen.clone());
+    lights.add(screen);
+
+    (BVHNode::new(&mut world, 0.0, 1.0), lights)
+}
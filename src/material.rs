@@ -1,7 +1,10 @@
 use crate::hittable::HitRecord;
+use crate::medium::*;
 use crate::ray::Ray;
-use crate::rtweekend::random_double;
+use crate::rtweekend::*;
+use crate::spectrum::*;
 use crate::texture::*;
+use crate::thin_film::*;
 use crate::vec3::*;
 use std::sync::Arc;
 
@@ -14,9 +17,25 @@ pub trait Material: Send + Sync {
         scattered: &mut Ray,
     ) -> bool;
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+
+    // BSDF times |cos theta| toward `scattered`, for materials that can be evaluated in any
+    // direction. Specular materials leave it at zero.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::zero()
+    }
+
+    // density of scatter() choosing `scattered`, zero for specular materials
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    // surfaces that bound a medium or a refractive volume describe it here
+    fn interface(&self, _rec: &HitRecord) -> Option<MediumInterface> {
+        None
+    }
 }
 
 pub struct Lambertian {
@@ -45,21 +64,47 @@ impl Material for Lambertian {
             dire: scatter_direction,
             tm: r_in.tm,
         };
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.albedo.value_at(rec);
         true
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.albedo.value_at(rec) * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = rec.normal * scattered.dire.unit();
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
 }
 
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f64,
+    pub albedo: Arc<dyn Texture>,
+    pub fuzz: Arc<dyn Texture>, // read from the first channel, clamped to 1
+    pub film: Option<ThinFilm>, // coating, e.g. anodized or heat tinted metal
 }
 
 impl Metal {
     pub fn new(a: &Color, f: f64) -> Self {
+        Self::new_from_texture(Arc::new(SolidColor::new(*a)), scalar(f))
+    }
+
+    pub fn new_from_texture(albedo: Arc<dyn Texture>, fuzz: Arc<dyn Texture>) -> Self {
         Self {
-            albedo: *a,
-            fuzz: if f < 1.0 { f } else { 1.0 },
+            albedo,
+            fuzz,
+            film: None,
+        }
+    }
+
+    pub fn new_with_film(a: &Color, f: f64, film: ThinFilm) -> Self {
+        Self {
+            film: Some(film),
+            ..Self::new(a, f)
         }
     }
 }
@@ -72,24 +117,82 @@ impl Material for Metal {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
+        let fuzz = self.fuzz.value_at(rec).x.min(1.0);
         let reflected = reflect(&(r_in.dire.unit()), &(rec.normal));
         *scattered = Ray {
             orig: rec.p,
-            dire: reflected + random_in_unit_sphere() * self.fuzz,
+            dire: reflected + random_in_unit_sphere() * fuzz,
             tm: r_in.tm,
         };
-        *attenuation = self.albedo;
+        *attenuation = self.albedo.value_at(rec);
+        if let Some(film) = &self.film {
+            // the albedo is the reflectance of the bare metal at normal incidence
+            let (eta, k) = conductor_from_reflectance(attenuation);
+            let cos_theta = -(r_in.dire.unit() * rec.normal);
+            *attenuation = film.reflectance(rec, cos_theta, rec.outer_ior, &eta, &k);
+        }
         scattered.dire * rec.normal > 0.0
     }
 }
 
 pub struct Dielectric {
-    ref_idx: f64,
+    ref_idx: Arc<dyn Texture>, // read from the first channel
+    priority: i32,
+    interior: Option<Arc<dyn Medium>>,
+    pub film: Option<ThinFilm>, // coating, e.g. soap bubbles (IOR 1 inside) or oil on water
+    pub dispersion: Option<Dispersion>, // replaces ref_idx in spectral mode
 }
 
 impl Dielectric {
     pub fn new(ri: f64) -> Self {
-        Self { ref_idx: ri }
+        Self::new_nested(ri, 0, None)
+    }
+
+    pub fn new_with_medium(ri: f64, interior: Arc<dyn Medium>) -> Self {
+        Self::new_nested(ri, 0, Some(interior))
+    }
+
+    // for overlapping volumes, e.g. water in a glass gets a lower priority than the glass
+    pub fn new_nested(ri: f64, priority: i32, interior: Option<Arc<dyn Medium>>) -> Self {
+        Self {
+            ref_idx: scalar(ri),
+            priority,
+            interior,
+            film: None,
+            dispersion: None,
+        }
+    }
+
+    // IOR at the d line outside of spectral mode
+    pub fn new_with_dispersion(dispersion: Dispersion) -> Self {
+        Self {
+            dispersion: Some(dispersion),
+            ..Self::new(dispersion.ior(LAMBDA_D))
+        }
+    }
+
+    pub fn new_with_film(ri: f64, film: ThinFilm) -> Self {
+        Self {
+            film: Some(film),
+            ..Self::new(ri)
+        }
+    }
+
+    pub fn new_from_texture(ri: Arc<dyn Texture>) -> Self {
+        Self {
+            ref_idx: ri,
+            priority: 0,
+            interior: None,
+            film: None,
+            dispersion: None,
+        }
+    }
+
+    fn ior(&self, rec: &HitRecord) -> f64 {
+        match (&self.dispersion, rec.wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ref_idx.value_at(rec).x,
+        }
     }
 }
 
@@ -102,10 +205,11 @@ impl Material for Dielectric {
         scattered: &mut Ray,
     ) -> bool {
         *attenuation = Color::new(1.0, 1.0, 1.0);
+        let ior = self.ior(rec);
         let etai_over_etat = if rec.front_face {
-            1.0 / self.ref_idx
+            rec.outer_ior / ior
         } else {
-            self.ref_idx
+            ior / rec.outer_ior
         };
         let unit_direction = r_in.dire.unit();
 
@@ -121,6 +225,35 @@ impl Material for Dielectric {
             };
             return true;
         }
+        if let Some(film) = &self.film {
+            // colored reflectance, the film itself doesn't bend the refracted ray
+            let (eta_i, eta_t) = if rec.front_face {
+                (rec.outer_ior, ior)
+            } else {
+                (ior, rec.outer_ior)
+            };
+            let r = film.reflectance(
+                rec,
+                cos_theta,
+                eta_i,
+                &(Color::ones() * eta_t),
+                &Color::zero(),
+            );
+            let reflect_prob = (r.x + r.y + r.z) / 3.0;
+            let dire = if random_double(0.0, 1.0) < reflect_prob {
+                *attenuation = r / reflect_prob;
+                reflect(&unit_direction, &rec.normal)
+            } else {
+                *attenuation = (Color::ones() - r) / (1.0 - reflect_prob);
+                refract(unit_direction, rec.normal, etai_over_etat)
+            };
+            *scattered = Ray {
+                orig: rec.p,
+                dire,
+                tm: r_in.tm,
+            };
+            return true;
+        }
         let reflect_prob = schlick(cos_theta, etai_over_etat);
         if random_double(0.0, 1.0) < reflect_prob {
             let reflected = reflect(&unit_direction, &rec.normal);
@@ -139,6 +272,14 @@ impl Material for Dielectric {
         };
         true
     }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        Some(MediumInterface {
+            ior: self.ior(rec),
+            priority: self.priority,
+            interior: self.interior.clone(),
+        })
+    }
 }
 
 pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
@@ -173,26 +314,376 @@ impl Material for DiffuseLight {
     ) -> bool {
         false
     }
-    fn emitted(&self, u: f64, v: f64, p: &Point) -> Color {
-        self.emit.value(u, v, p)
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        self.emit.value_at(rec)
     }
 }
 
-pub struct Isotropic {
-    pub albedo: Arc<dyn Texture>,
+// Different materials on the two sides of a surface, e.g. a panel that only emits on its
+// front or a sheet of paper printed on one side. Sides follow HitRecord::front_face.
+pub struct TwoSided {
+    pub front: Arc<dyn Material>,
+    pub back: Arc<dyn Material>,
 }
 
-impl Isotropic {
-    pub fn new(a: Arc<dyn Texture>) -> Self {
-        Self { albedo: a }
+impl TwoSided {
+    pub fn new(front: Arc<dyn Material>, back: Arc<dyn Material>) -> Self {
+        Self { front, back }
     }
 
-    pub fn new_from_color(c: Color) -> Self {
-        Self::new(Arc::new(SolidColor::new(c)))
+    fn side(&self, rec: &HitRecord) -> &dyn Material {
+        if rec.front_face {
+            self.front.as_ref()
+        } else {
+            self.back.as_ref()
+        }
+    }
+}
+
+impl Material for TwoSided {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.side(rec).scatter(r_in, rec, attenuation, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.side(rec).emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.side(rec).eval(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.side(rec).scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        self.side(rec).interface(rec)
+    }
+}
+
+// exact unpolarized Fresnel reflectance of a dielectric interface
+pub fn fr_dielectric(cos_theta_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let mut cos_theta_i = clamp(cos_theta_i, -1.0, 1.0);
+    let (mut eta_i, mut eta_t) = (eta_i, eta_t);
+    if cos_theta_i <= 0.0 {
+        std::mem::swap(&mut eta_i, &mut eta_t);
+        cos_theta_i = cos_theta_i.abs();
+    }
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let sin_theta_t = eta_i / eta_t * sin_theta_i;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+    let r_parl =
+        (eta_t * cos_theta_i - eta_i * cos_theta_t) / (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let r_perp =
+        (eta_i * cos_theta_i - eta_t * cos_theta_t) / (eta_i * cos_theta_i + eta_t * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+pub fn luminance(c: &Color) -> f64 {
+    0.212_671 * c.x + 0.715_160 * c.y + 0.072_169 * c.z
+}
+
+fn color_exp(c: Color) -> Color {
+    Color::new(c.x.exp(), c.y.exp(), c.z.exp())
+}
+
+// Marschner style hair scattering after d'Eon et al. and pbrt: longitudinal lobes M_p,
+// azimuthal lobes N_p and attenuations A_p for R, TT, TRT and the remaining higher orders.
+// Unlike the other materials its parameters are not textures: the lobe variances and scale
+// tilts are derived from them once in new, and hair strands are too thin to need texturing
+// along them anyway. Vary the material per strand instead.
+const P_MAX: usize = 3;
+
+pub struct Hair {
+    sigma_a: Color,
+    eta: f64,
+    v: [f64; P_MAX + 1],
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+    clamp(x, -1.0, 1.0).asin()
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+fn hair_mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_bessel_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * bessel_i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+#[allow(clippy::many_single_char_names)]
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    clamp(x, a, b)
+}
+
+fn hair_phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn hair_np(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi - hair_phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+impl Hair {
+    // beta_m and beta_n are the longitudinal and azimuthal roughness in [0, 1],
+    // alpha is the tilt of the cuticle scales in degrees
+    pub fn new(sigma_a: Color, eta: f64, beta_m: f64, beta_n: f64, alpha: f64) -> Self {
+        let v0 = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        let s =
+            0.626_657_069 * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [0.0; 3];
+        let mut cos_2k_alpha = [0.0; 3];
+        sin_2k_alpha[0] = degrees_to_radians(alpha).sin();
+        cos_2k_alpha[0] = safe_sqrt(1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]);
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+        Self {
+            sigma_a,
+            eta,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // absorption from eumelanin and pheomelanin concentrations
+    pub fn new_from_melanin(eumelanin: f64, pheomelanin: f64, beta_m: f64, beta_n: f64) -> Self {
+        let sigma_a =
+            Color::new(0.419, 0.697, 1.37) * eumelanin + Color::new(0.187, 0.4, 1.05) * pheomelanin;
+        Self::new(sigma_a, 1.55, beta_m, beta_n, 2.0)
+    }
+
+    // absorption that gives roughly the requested diffuse color
+    pub fn new_from_color(c: Color, beta_m: f64, beta_n: f64) -> Self {
+        let d = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        let channel = |x: f64| (x.max(1e-4).ln() / d).powi(2);
+        let sigma_a = Color::new(channel(c.x), channel(c.y), channel(c.z));
+        Self::new(sigma_a, 1.55, beta_m, beta_n, 2.0)
+    }
+
+    fn ap(&self, cos_theta_o: f64, h: f64, t: Color) -> [Color; P_MAX + 1] {
+        let cos_gamma_o = safe_sqrt(1.0 - h * h);
+        let cos_theta = cos_theta_o * cos_gamma_o;
+        let f = fr_dielectric(cos_theta, 1.0, self.eta);
+        let a0 = Color::ones() * f;
+        let a1 = t * (1.0 - f) * (1.0 - f);
+        let a2 = Vec3::elemul(a1, t) * f;
+        let tf = t * f;
+        let a3 = Vec3::elemul(
+            Vec3::elemul(a2, tf),
+            Color::new(1.0 / (1.0 - tf.x), 1.0 / (1.0 - tf.y), 1.0 / (1.0 - tf.z)),
+        );
+        [a0, a1, a2, a3]
+    }
+
+    // (sin, cos) of theta_o rotated by the scale tilt of lobe p
+    fn tilt(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin_op, cos_op) = match p {
+            0 => (
+                sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1],
+            ),
+            1 => (
+                sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0],
+            ),
+            2 => (
+                sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2],
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin_op, cos_op.abs())
+    }
+
+    fn transmittance(&self, sin_theta_o: f64, h: f64) -> (Color, f64, f64) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let gamma_t = safe_asin(sin_gamma_t);
+        let t = color_exp(-self.sigma_a * (2.0 * cos_gamma_t / cos_theta_t));
+        (t, gamma_t, cos_theta_o)
+    }
+
+    fn ap_pdf(&self, ap: &[Color; P_MAX + 1]) -> [f64; P_MAX + 1] {
+        let sum: f64 = ap.iter().map(luminance).sum();
+        let mut pdf = [0.0; P_MAX + 1];
+        for i in 0..=P_MAX {
+            pdf[i] = if sum > 0.0 {
+                luminance(&ap[i]) / sum
+            } else {
+                1.0 / (P_MAX + 1) as f64
+            };
+        }
+        pdf
+    }
+
+    // BSDF value and pdf for local directions, x is along the hair
+    fn f_pdf(&self, wo: &Vec3, wi: &Vec3, h: f64) -> (Color, f64) {
+        let sin_theta_o = wo.x;
+        let phi_o = wo.z.atan2(wo.y);
+        let sin_theta_i = wi.x;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let phi_i = wi.z.atan2(wi.y);
+        let gamma_o = safe_asin(h);
+
+        let (t, gamma_t, cos_theta_o) = self.transmittance(sin_theta_o, h);
+        let phi = phi_i - phi_o;
+        let ap = self.ap(cos_theta_o, h, t);
+        let ap_pdf = self.ap_pdf(&ap);
+
+        let mut f = Color::zero();
+        let mut pdf = 0.0;
+        for p in 0..P_MAX {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let mp = hair_mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p]);
+            let np = hair_np(phi, p, self.s, gamma_o, gamma_t);
+            f += ap[p] * (mp * np);
+            pdf += mp * ap_pdf[p] * np;
+        }
+        let mp = hair_mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        );
+        f += ap[P_MAX] * (mp / (2.0 * PI));
+        pdf += mp * ap_pdf[P_MAX] / (2.0 * PI);
+        (f, pdf)
+    }
+
+    fn sample_wi(&self, wo: &Vec3, h: f64) -> Vec3 {
+        let sin_theta_o = wo.x;
+        let phi_o = wo.z.atan2(wo.y);
+        let gamma_o = safe_asin(h);
+        let (t, gamma_t, cos_theta_o) = self.transmittance(sin_theta_o, h);
+        let ap_pdf = self.ap_pdf(&self.ap(cos_theta_o, h, t));
+
+        // pick a lobe
+        let mut u = random_double(0.0, 1.0);
+        let mut p = 0;
+        while p < P_MAX && u >= ap_pdf[p] {
+            u -= ap_pdf[p];
+            p += 1;
+        }
+        let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+
+        // sample M_p
+        let u1 = random_double(0.0, 1.0).max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u1 + (1.0 - u1) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * random_double(0.0, 1.0)).cos();
+        let sin_theta_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        // sample N_p
+        let dphi = if p < P_MAX {
+            hair_phi(p, gamma_o, gamma_t)
+                + sample_trimmed_logistic(random_double(0.0, 1.0), self.s, -PI, PI)
+        } else {
+            2.0 * PI * random_double(0.0, 1.0)
+        };
+        let phi_i = phi_o + dphi;
+        Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        )
+    }
+
+    fn local_f_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> (Color, f64) {
+        let frame = Self::frame(r_in, rec);
+        let to_local = |w: Vec3| Vec3::new(w * frame[0], w * frame[1], w * frame[2]);
+        let wo = to_local(-r_in.dire.unit());
+        let wi = to_local(scattered.dire.unit());
+        self.f_pdf(&wo, &wi, -1.0 + 2.0 * rec.v)
+    }
+
+    // frame with x along the hair and z facing the ray, as for a flat curve
+    fn frame(r_in: &Ray, rec: &HitRecord) -> [Vec3; 3] {
+        let x = rec.dpdu.unit();
+        let y = Vec3::cross(r_in.dire.unit(), x).unit();
+        let z = Vec3::cross(x, y);
+        [x, y, z]
     }
 }
 
-impl Material for Isotropic {
+impl Material for Hair {
     fn scatter(
         &self,
         r_in: &Ray,
@@ -200,12 +691,127 @@ impl Material for Isotropic {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
+        let frame = Self::frame(r_in, rec);
+        let h = -1.0 + 2.0 * rec.v;
+        let wo_world = -r_in.dire.unit();
+        let wo = Vec3::new(
+            wo_world * frame[0],
+            wo_world * frame[1],
+            wo_world * frame[2],
+        );
+        let wi = self.sample_wi(&wo, h);
+        let (f, pdf) = self.f_pdf(&wo, &wi, h);
+        if pdf <= 0.0 {
+            return false;
+        }
         *scattered = Ray {
             orig: rec.p,
-            dire: random_in_unit_sphere(),
+            dire: frame[0] * wi.x + frame[1] * wi.y + frame[2] * wi.z,
             tm: r_in.tm,
         };
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        // the hair BSDF is defined with a 1 / |cos theta_i| that cancels the cosine term
+        *attenuation = f / pdf;
         true
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.local_f_pdf(r_in, rec, scattered).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.local_f_pdf(r_in, rec, scattered).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{Hittable, Sphere};
+
+    #[test]
+    fn test_two_sided_picks_side() {
+        // a sphere that only glows inside
+        let mat = Arc::new(TwoSided::new(
+            Arc::new(Lambertian::new(Color::ones())),
+            Arc::new(DiffuseLight::new_from_color(Color::new(2.0, 2.0, 2.0))),
+        ));
+        let sphere = Sphere::new(Point::zero(), 1.0, mat.clone());
+        let mut rec = HitRecord::new(mat);
+        let dire = Vec3::new(0.0, 0.0, -1.0);
+        for (z, glows) in [(5.0, false), (0.0, true)].iter() {
+            let r = Ray {
+                orig: Point::new(0.0, 0.0, *z),
+                dire,
+                tm: 0.0,
+            };
+            assert!(sphere.hit(&r, 0.001, INFINITY, &mut rec));
+            assert_eq!(rec.front_face, !glows);
+            let emitted = rec.mat_ptr.emitted(&r, &rec);
+            assert!((emitted.x - if *glows { 2.0 } else { 0.0 }).abs() < 1e-12);
+            let mut attenuation = Color::zero();
+            let mut scattered = r.clone();
+            assert_eq!(
+                rec.mat_ptr
+                    .scatter(&r, &rec, &mut attenuation, &mut scattered),
+                !glows
+            );
+        }
+    }
+
+    #[test]
+    fn test_hair_mp_normalized() {
+        // each longitudinal lobe integrates to one over theta_i with the cosine
+        let steps = 20000;
+        for v in [0.1, 0.3, 0.5, 0.9].iter() {
+            for theta_o in [-1.2, -0.3, 0.0, 0.7].iter() {
+                let theta_o: f64 = *theta_o;
+                let mut integral = 0.0;
+                for k in 0..steps {
+                    let theta_i = -PI / 2.0 + PI * (k as f64 + 0.5) / steps as f64;
+                    let mp = hair_mp(
+                        theta_i.cos(),
+                        theta_o.cos(),
+                        theta_i.sin(),
+                        theta_o.sin(),
+                        *v,
+                    );
+                    integral += mp * theta_i.cos() * PI / steps as f64;
+                }
+                assert!((integral - 1.0).abs() < 5e-3, "v {} {}", v, integral);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hair_white_furnace() {
+        // without absorption the attenuations share all of the light
+        let hair = Hair::new(Color::zero(), 1.55, 0.5, 0.5, 2.0);
+        for h in [-0.9, 0.0, 0.6].iter() {
+            let ap = hair.ap(0.8, *h, Color::ones());
+            let sum = ap.iter().fold(Color::zero(), |s, a| s + *a);
+            assert!((sum.x - 1.0).abs() < 1e-9 && (sum.z - 1.0).abs() < 1e-9);
+        }
+
+        // and the whole BSDF scatters all of it
+        let samples = 200000;
+        let wo = Vec3::new(0.3, 0.8, 0.52).unit();
+        let mut total = Color::zero();
+        for _ in 0..samples {
+            let wi = random_unit_vector();
+            let h = random_double(-1.0, 1.0);
+            total += hair.f_pdf(&wo, &wi, h).0 * (4.0 * PI / samples as f64);
+        }
+        assert!((total.y - 1.0).abs() < 0.03, "{:?}", total);
+
+        // absorption only takes light away
+        let brown = Hair::new_from_melanin(1.3, 0.0, 0.5, 0.5);
+        for h in [-0.9, 0.0, 0.6].iter() {
+            let (t, _, cos_theta_o) = brown.transmittance(0.3, *h);
+            let sum = brown
+                .ap(cos_theta_o, *h, t)
+                .iter()
+                .fold(0.0, |s, a| s + a.x);
+            assert!(sum < 1.0);
+        }
+    }
 }
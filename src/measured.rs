@@ -0,0 +1,176 @@
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::principled::random_cosine_direction;
+use crate::ray::Ray;
+use crate::rtweekend::*;
+use crate::vec3::*;
+use std::fs;
+
+// resolution of the MERL tables in theta_half, theta_diff and phi_diff
+const THETA_H_RES: usize = 90;
+const THETA_D_RES: usize = 90;
+const PHI_D_RES: usize = 180;
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// probability of sampling the glossy lobe instead of the cosine weighted hemisphere
+const GLOSSY_WEIGHT: f64 = 0.5;
+
+// Isotropic BRDF measured by Matusik et al. 2003 (the MERL database), stored in the
+// Rusinkiewicz half/difference angles. Sampling mixes a cosine lobe with a GGX lobe around
+// the mirror direction, so very shiny samples still converge slowly.
+pub struct MeasuredBrdf {
+    data: Vec<f32>, // red, green and blue tables, already scaled
+    glossy: TrowbridgeReitz,
+}
+
+impl MeasuredBrdf {
+    // a .binary file: three i32 dimensions then the f64 tables, little endian
+    pub fn load(filename: &str) -> Self {
+        let bytes = fs::read(filename).expect("failed to read the BRDF");
+        let dim = |i: usize| {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&bytes[4 * i..4 * i + 4]);
+            i32::from_le_bytes(b) as usize
+        };
+        let n = dim(0) * dim(1) * dim(2);
+        assert_eq!(n, THETA_H_RES * THETA_D_RES * PHI_D_RES, "not a MERL BRDF");
+        assert_eq!(bytes.len(), 12 + 3 * n * 8, "truncated BRDF");
+
+        let mut data = Vec::with_capacity(3 * n);
+        for (i, chunk) in bytes[12..].chunks(8).enumerate() {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(chunk);
+            data.push((f64::from_le_bytes(b) * SCALE[i / n]).max(0.0) as f32);
+        }
+        Self::new(data)
+    }
+
+    pub fn new(data: Vec<f32>) -> Self {
+        assert_eq!(data.len(), 3 * THETA_H_RES * THETA_D_RES * PHI_D_RES);
+        Self {
+            data,
+            glossy: TrowbridgeReitz::new(0.2, 0.2),
+        }
+    }
+
+    // BRDF for local directions around +z
+    pub fn f(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zero();
+        }
+        let (theta_h, theta_d, phi_d) = half_diff(wo, wi);
+
+        // theta_half is stored on a square root scale, denser near the highlight
+        let th = ((theta_h / (PI / 2.0)).max(0.0).sqrt() * THETA_H_RES as f64) as usize;
+        let td = (theta_d / (PI / 2.0) * THETA_D_RES as f64) as usize;
+        // reciprocity: phi_diff and phi_diff + pi are the same
+        let phi_d = if phi_d < 0.0 { phi_d + PI } else { phi_d };
+        let pd = (phi_d / PI * PHI_D_RES as f64) as usize;
+        let i = pd.min(PHI_D_RES - 1)
+            + td.min(THETA_D_RES - 1) * PHI_D_RES
+            + th.min(THETA_H_RES - 1) * PHI_D_RES * THETA_D_RES;
+
+        let n = THETA_H_RES * THETA_D_RES * PHI_D_RES;
+        Color::new(
+            self.data[i] as f64,
+            self.data[i + n] as f64,
+            self.data[i + 2 * n] as f64,
+        )
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = (*wo + *wi).unit();
+        let glossy = self.glossy.d_visible(wo, &wm) / (4.0 * (*wo * wm));
+        (1.0 - GLOSSY_WEIGHT) * wi.z / PI + GLOSSY_WEIGHT * glossy
+    }
+}
+
+fn rotate(v: &Vec3, axis: &Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    *v * cos + Vec3::cross(*axis, *v) * sin + *axis * ((*axis * *v) * (1.0 - cos))
+}
+
+// (theta_half, theta_diff, phi_diff) of a pair of directions, Rusinkiewicz 1998
+pub fn half_diff(wo: &Vec3, wi: &Vec3) -> (f64, f64, f64) {
+    let h = (*wo + *wi).unit();
+    let theta_h = clamp(h.z, -1.0, 1.0).acos();
+    let phi_h = h.y.atan2(h.x);
+    let diff = rotate(
+        &rotate(wi, &Vec3::new(0.0, 0.0, 1.0), -phi_h),
+        &Vec3::new(0.0, 1.0, 0.0),
+        -theta_h,
+    );
+    (
+        theta_h,
+        clamp(diff.z, -1.0, 1.0).acos(),
+        diff.y.atan2(diff.x),
+    )
+}
+
+impl Material for MeasuredBrdf {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z <= 0.0 {
+            return false;
+        }
+        let wi = if random_double(0.0, 1.0) < GLOSSY_WEIGHT {
+            reflect_local(&wo, &self.glossy.sample_wm(&wo))
+        } else {
+            random_cosine_direction()
+        };
+        let pdf = self.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+        *attenuation = self.f(&wo, &wi) * (wi.z / pdf);
+        *scattered = Ray {
+            orig: rec.p,
+            dire: frame.local(&wi),
+            tm: r_in.tm,
+        };
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        self.f(&wo, &wi) * wi.z.max(0.0)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        self.pdf(&wo, &wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_diff() {
+        // mirror directions have the half vector on the normal
+        let wo = Vec3::new(0.3, 0.4, 0.866).unit();
+        let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+        let (theta_h, theta_d, _) = half_diff(&wo, &wi);
+        assert!(theta_h.abs() < 1e-6);
+        assert!((theta_d - wo.z.acos()).abs() < 1e-6);
+
+        // theta_diff is half the angle between the directions
+        let wo = Vec3::new(0.5, 0.0, 0.8).unit();
+        let wi = Vec3::new(0.0, 0.6, 0.7).unit();
+        let (_, theta_d, _) = half_diff(&wo, &wi);
+        assert!((2.0 * theta_d - (wo * wi).acos()).abs() < 1e-6);
+    }
+}
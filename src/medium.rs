@@ -0,0 +1,197 @@
+use crate::phase::*;
+use crate::ray::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// Medium filling the inside of a surface, declared by the surface's material through
+// Material::interface and applied by the integrator to every path segment inside it.
+pub trait Medium: Send + Sync {
+    // Samples a scattering point on r before t_max. Returns its t (None when the ray gets
+    // through to t_max) and the weight of the sample.
+    fn sample(&self, r: &Ray, t_max: f64) -> (Option<f64>, Color);
+    fn phase_function(&self) -> &dyn PhaseFunction;
+}
+
+pub struct HomogeneousMedium {
+    sigma_a: Color,
+    sigma_s: Color,
+    phase_function: Arc<dyn PhaseFunction>,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Color, sigma_s: Color) -> Self {
+        Self::new_with_phase(sigma_a, sigma_s, Arc::new(Isotropic::new()))
+    }
+
+    pub fn new_with_phase(
+        sigma_a: Color,
+        sigma_s: Color,
+        phase_function: Arc<dyn PhaseFunction>,
+    ) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            phase_function,
+        }
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn sample(&self, r: &Ray, t_max: f64) -> (Option<f64>, Color) {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let ray_length = r.dire.length();
+
+        // sample the distance with one channel's extinction, weight by the average pdf
+        let channel = random_int(0, 2) as usize;
+        let dist = if sigma_t[channel] > 0.0 {
+            -(1.0 - random_double(0.0, 1.0)).ln() / sigma_t[channel]
+        } else {
+            INFINITY
+        };
+        let sampled = dist < t_max * ray_length;
+        let dist = dist.min(t_max * ray_length);
+        let tr = Color::new(
+            (-sigma_t.x * dist).exp(),
+            (-sigma_t.y * dist).exp(),
+            (-sigma_t.z * dist).exp(),
+        );
+
+        let density = if sampled {
+            Vec3::elemul(sigma_t, tr)
+        } else {
+            tr
+        };
+        let pdf = (density.x + density.y + density.z) / 3.0;
+        if pdf <= 0.0 {
+            return (None, Color::zero());
+        }
+        if sampled {
+            (
+                Some(dist / ray_length),
+                Vec3::elemul(tr, self.sigma_s) / pdf,
+            )
+        } else {
+            (None, tr / pdf)
+        }
+    }
+
+    fn phase_function(&self) -> &dyn PhaseFunction {
+        self.phase_function.as_ref()
+    }
+}
+
+// What a surface separates from the outside. Where interfaces overlap (water poured into a
+// glass) the one with the higher priority wins and hits on the other are skipped.
+#[derive(Clone)]
+pub struct MediumInterface {
+    pub ior: f64,
+    pub priority: i32,
+    pub interior: Option<Arc<dyn Medium>>,
+}
+
+#[derive(Clone)]
+struct StackEntry {
+    id: usize,
+    interface: MediumInterface,
+}
+
+// The interfaces a path is currently inside of. Paths start outside of everything (vacuum).
+#[derive(Clone, Default)]
+pub struct MediumStack {
+    entries: Vec<StackEntry>,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    // highest priority entry, the latest one entered on ties
+    fn top(&self, skip: Option<usize>) -> Option<&StackEntry> {
+        let mut top: Option<&StackEntry> = None;
+        for (i, e) in self.entries.iter().enumerate() {
+            if Some(i) == skip {
+                continue;
+            }
+            top = match top {
+                Some(t) if t.interface.priority > e.interface.priority => Some(t),
+                _ => Some(e),
+            };
+        }
+        top
+    }
+
+    fn position(&self, id: usize) -> Option<usize> {
+        self.entries.iter().rposition(|e| e.id == id)
+    }
+
+    pub fn current_medium(&self) -> Option<Arc<dyn Medium>> {
+        self.top(None).and_then(|e| e.interface.interior.clone())
+    }
+
+    // IOR on the other side of a hit on the interface `id`, or None when the hit is inside a
+    // higher priority interface and should be ignored
+    pub fn outer_ior(
+        &self,
+        id: usize,
+        interface: &MediumInterface,
+        front_face: bool,
+    ) -> Option<f64> {
+        let other = if front_face {
+            self.top(None)
+        } else {
+            self.top(self.position(id))
+        };
+        match other {
+            Some(e) if e.interface.priority > interface.priority => None,
+            Some(e) => Some(e.interface.ior),
+            None => Some(1.0),
+        }
+    }
+
+    // the path went through the interface, entering it when it hit the front face
+    pub fn cross(&mut self, id: usize, interface: &MediumInterface, front_face: bool) {
+        if front_face {
+            self.entries.push(StackEntry {
+                id,
+                interface: interface.clone(),
+            });
+        } else if let Some(i) = self.position(id) {
+            self.entries.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(ior: f64, priority: i32) -> MediumInterface {
+        MediumInterface {
+            ior,
+            priority,
+            interior: None,
+        }
+    }
+
+    #[test]
+    fn test_nested_priorities() {
+        // water (priority 1) overlapping the walls of a glass (priority 2)
+        let glass = interface(1.5, 2);
+        let water = interface(1.33, 1);
+        let mut stack = MediumStack::new();
+
+        assert_eq!(stack.outer_ior(1, &glass, true), Some(1.0));
+        stack.cross(1, &glass, true);
+        // the water surface inside the glass wall is a false hit
+        assert_eq!(stack.outer_ior(2, &water, true), None);
+        stack.cross(2, &water, true);
+        // leaving the glass wall into the water
+        assert_eq!(stack.outer_ior(1, &glass, false), Some(1.33));
+        stack.cross(1, &glass, false);
+        assert_eq!(stack.outer_ior(2, &water, false), Some(1.0));
+        stack.cross(2, &water, false);
+        assert!(stack.entries.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::hittable::*;
+use crate::material::*;
+use crate::triangle::*;
+use crate::vec3::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Quad mesh refined with Catmull-Clark subdivision, tessellated into triangles for the BVH.
+#[derive(Clone)]
+pub struct QuadMesh {
+    pub vertices: Vec<Point>,
+    pub faces: Vec<[usize; 4]>,     // counter-clockwise seen from outside
+    pub face_materials: Vec<usize>, // per face index into the materials of to_triangles_with
+}
+
+impl QuadMesh {
+    pub fn new(vertices: Vec<Point>, faces: Vec<[usize; 4]>) -> Self {
+        let face_materials = vec![0; faces.len()];
+        Self::new_with_materials(vertices, faces, face_materials)
+    }
+
+    pub fn new_with_materials(
+        vertices: Vec<Point>,
+        faces: Vec<[usize; 4]>,
+        face_materials: Vec<usize>,
+    ) -> Self {
+        assert_eq!(faces.len(), face_materials.len());
+        Self {
+            vertices,
+            faces,
+            face_materials,
+        }
+    }
+
+    // faces are +z, -z, +y, -y, +x, -x with material indices 0 to 5 in that order, as the
+    // sides of Box6::new_with_sides
+    pub fn new_box(p0: &Point, p1: &Point) -> Self {
+        let vertices = vec![
+            Point::new(p0.x, p0.y, p0.z),
+            Point::new(p1.x, p0.y, p0.z),
+            Point::new(p1.x, p1.y, p0.z),
+            Point::new(p0.x, p1.y, p0.z),
+            Point::new(p0.x, p0.y, p1.z),
+            Point::new(p1.x, p0.y, p1.z),
+            Point::new(p1.x, p1.y, p1.z),
+            Point::new(p0.x, p1.y, p1.z),
+        ];
+        let faces = vec![
+            [4, 5, 6, 7],
+            [0, 3, 2, 1],
+            [3, 7, 6, 2],
+            [0, 1, 5, 4],
+            [1, 2, 6, 5],
+            [0, 4, 7, 3],
+        ];
+        Self::new_with_materials(vertices, faces, (0..6).collect())
+    }
+
+    fn edge_key(a: usize, b: usize) -> (usize, usize) {
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    // one Catmull-Clark step, every quad becomes four
+    #[allow(clippy::many_single_char_names)]
+    pub fn subdivide(&self) -> Self {
+        let face_points: Vec<Point> = self
+            .faces
+            .iter()
+            .map(|f| {
+                (self.vertices[f[0]]
+                    + self.vertices[f[1]]
+                    + self.vertices[f[2]]
+                    + self.vertices[f[3]])
+                    / 4.0
+            })
+            .collect();
+
+        // faces adjacent to every edge
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (fi, f) in self.faces.iter().enumerate() {
+            for k in 0..4 {
+                edge_faces
+                    .entry(Self::edge_key(f[k], f[(k + 1) % 4]))
+                    .or_default()
+                    .push(fi);
+            }
+        }
+
+        let mut vertices = Vec::new();
+
+        // edge points
+        let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+        for (key, faces) in edge_faces.iter() {
+            let mid = (self.vertices[key.0] + self.vertices[key.1]) / 2.0;
+            let edge_point = if faces.len() == 2 {
+                (mid * 2.0 + face_points[faces[0]] + face_points[faces[1]]) / 4.0
+            } else {
+                mid // boundary edge
+            };
+            edge_index.insert(
+                *key,
+                self.vertices.len() + face_points.len() + vertices.len(),
+            );
+            vertices.push(edge_point);
+        }
+
+        // moved original vertices
+        let n = self.vertices.len();
+        let mut face_sum = vec![Point::zero(); n];
+        let mut valence = vec![0; n];
+        for (fi, f) in self.faces.iter().enumerate() {
+            for v in f.iter() {
+                face_sum[*v] += face_points[fi];
+                valence[*v] += 1;
+            }
+        }
+        let mut edge_sum = vec![Point::zero(); n];
+        let mut edge_count = vec![0; n];
+        let mut boundary_sum = vec![Point::zero(); n];
+        let mut boundary_count = vec![0; n];
+        for (key, faces) in edge_faces.iter() {
+            let mid = (self.vertices[key.0] + self.vertices[key.1]) / 2.0;
+            for v in [key.0, key.1].iter() {
+                edge_sum[*v] += mid;
+                edge_count[*v] += 1;
+            }
+            if faces.len() != 2 {
+                boundary_sum[key.0] += self.vertices[key.1];
+                boundary_sum[key.1] += self.vertices[key.0];
+                boundary_count[key.0] += 1;
+                boundary_count[key.1] += 1;
+            }
+        }
+        let mut new_vertices = Vec::with_capacity(n + face_points.len() + vertices.len());
+        for (i, p) in self.vertices.iter().enumerate() {
+            let p = *p;
+            let moved = if boundary_count[i] == 2 {
+                (p * 6.0 + boundary_sum[i]) / 8.0
+            } else if boundary_count[i] > 0 || valence[i] == 0 {
+                p // corner or non-manifold vertex stays put
+            } else {
+                let k = valence[i] as f64;
+                let f = face_sum[i] / k;
+                let r = edge_sum[i] / edge_count[i] as f64;
+                (f + r * 2.0 + p * (k - 3.0)) / k
+            };
+            new_vertices.push(moved);
+        }
+        new_vertices.extend(face_points.iter());
+        new_vertices.extend(vertices.iter());
+
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        let mut face_materials = Vec::with_capacity(self.faces.len() * 4);
+        for (fi, f) in self.faces.iter().enumerate() {
+            let face_point = n + fi;
+            for k in 0..4 {
+                face_materials.push(self.face_materials[fi]);
+                let prev = f[(k + 3) % 4];
+                let next = f[(k + 1) % 4];
+                faces.push([
+                    f[k],
+                    edge_index[&Self::edge_key(f[k], next)],
+                    face_point,
+                    edge_index[&Self::edge_key(prev, f[k])],
+                ]);
+            }
+        }
+
+        Self::new_with_materials(new_vertices, faces, face_materials)
+    }
+
+    pub fn subdivide_levels(&self, levels: u32) -> Self {
+        let mut mesh = self.clone();
+        for _i in 0..levels {
+            mesh = mesh.subdivide();
+        }
+        mesh
+    }
+
+    // area weighted average of the adjacent face normals
+    #[allow(clippy::many_single_char_names)]
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::zero(); self.vertices.len()];
+        for f in self.faces.iter() {
+            let a = self.vertices[f[0]];
+            let b = self.vertices[f[1]];
+            let c = self.vertices[f[2]];
+            let d = self.vertices[f[3]];
+            let n = Vec3::cross(c - a, d - b);
+            for v in f.iter() {
+                normals[*v] += n;
+            }
+        }
+        normals
+            .into_iter()
+            .map(|n| {
+                if n.squared_length() > 0.0 {
+                    n.unit()
+                } else {
+                    Vec3::new(0.0, 1.0, 0.0)
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_triangles(&self, mat: Arc<dyn Material>) -> HittableList {
+        let mats = vec![mat; self.face_materials.iter().max().map_or(0, |m| m + 1)];
+        self.to_triangles_with(&mats)
+    }
+
+    // every face gets mats[face_materials[face]]
+    pub fn to_triangles_with(&self, mats: &[Arc<dyn Material>]) -> HittableList {
+        let normals = self.vertex_normals();
+        let mut list = HittableList::new();
+        for (f, m) in self.faces.iter().zip(self.face_materials.iter()) {
+            for tri in [[f[0], f[1], f[2]], [f[0], f[2], f[3]]].iter() {
+                list.add(Arc::new(Triangle::new_with_normals(
+                    self.vertices[tri[1]],
+                    self.vertices[tri[2]],
+                    self.vertices[tri[0]],
+                    [normals[tri[1]], normals[tri[2]], normals[tri[0]]],
+                    mats[*m].clone(),
+                )));
+            }
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subdivide_counts() {
+        let mesh = QuadMesh::new_box(&Point::zero(), &Point::ones()).subdivide_levels(2);
+        assert_eq!(mesh.faces.len(), 6 * 16);
+        // children keep the material of their face
+        assert_eq!(mesh.face_materials.iter().filter(|m| **m == 5).count(), 16);
+        // closed mesh: V - E + F = 2
+        assert_eq!(
+            mesh.vertices.len() as i32 - 2 * mesh.faces.len() as i32 + mesh.faces.len() as i32,
+            2
+        );
+    }
+
+    #[test]
+    fn test_box_side_order() {
+        // the same order as Box6::new_with_sides, outward faces
+        let mesh = QuadMesh::new_box(&Point::zero(), &Point::ones());
+        let axes = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+        ];
+        for (f, axis) in mesh.faces.iter().zip(axes.iter()) {
+            let v = |i: usize| mesh.vertices[f[i]];
+            let n = Vec3::cross(v(1) - v(0), v(2) - v(1)).unit();
+            assert!((n - *axis).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_subdivide_stays_in_hull() {
+        let mesh = QuadMesh::new_box(&Point::zero(), &Point::ones()).subdivide_levels(3);
+        for p in mesh.vertices.iter() {
+            for a in 0..3 {
+                assert!(p[a] >= 0.0 && p[a] <= 1.0);
+            }
+        }
+        // corners are pulled inwards
+        assert!(mesh.vertices[0].x > 0.0 && mesh.vertices[0].y > 0.0);
+    }
+}
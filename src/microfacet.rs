@@ -0,0 +1,537 @@
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::medium::*;
+use crate::onb::*;
+use crate::ray::Ray;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// below this alpha the surfaces are treated as perfectly smooth
+const SMOOTH_ALPHA: f64 = 1e-3;
+
+// GGX / Trowbridge-Reitz distribution of microfacet normals, in a local frame with z along
+// the shading normal.
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    // perceptually linear roughness in [0, 1], alpha = roughness^2
+    pub fn from_roughness(roughness: f64) -> Self {
+        let alpha = roughness * roughness;
+        Self::new(alpha, alpha)
+    }
+
+    pub fn smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z * wm.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let e = (wm.x * wm.x / (self.alpha_x * self.alpha_x)
+            + wm.y * wm.y / (self.alpha_y * self.alpha_y))
+            / cos2;
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos2 * cos2 * (1.0 + e) * (1.0 + e))
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        if w.z == 0.0 {
+            return INFINITY;
+        }
+        let alpha2_tan2 = (w.x * w.x * self.alpha_x * self.alpha_x
+            + w.y * w.y * self.alpha_y * self.alpha_y)
+            / (w.z * w.z);
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // density of the normals visible from w
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f64 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * (*w * *wm).abs()
+    }
+
+    // samples a visible normal (Heitz 2018)
+    pub fn sample_wm(&self, w: &Vec3) -> Vec3 {
+        let mut wh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).unit();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z < 0.99999 {
+            Vec3::cross(Vec3::new(0.0, 0.0, 1.0), wh).unit()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(wh, t1);
+
+        // uniform point on the disk, warped onto the visible half
+        let r = random_double(0.0, 1.0).sqrt();
+        let phi = random_double(0.0, 2.0 * PI);
+        let px = r * phi.cos();
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let py = (1.0 - s) * h + s * r * phi.sin();
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit()
+    }
+
+    // picks reflection or refraction on a sampled microfacet by its Fresnel reflectance
+    pub fn sample_dielectric(&self, wo: &Vec3, eta: f64) -> Option<Vec3> {
+        let wm = if self.smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.sample_wm(wo)
+        };
+        if random_double(0.0, 1.0) < fr_dielectric(*wo * wm, 1.0, eta) {
+            Some(reflect_local(wo, &wm))
+        } else {
+            refract_local(wo, &wm, eta)
+        }
+    }
+
+    // Rough dielectric BSDF times |cos theta_i| and the pdf of sample_dielectric choosing wi,
+    // in the local frame where wo.z > 0. eta is the IOR of the far side over that of wo's side.
+    pub fn dielectric_f_cos_pdf(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> (f64, f64) {
+        match self.dielectric_lobe(wo, wi, eta) {
+            Some((f_cos, pdf, fresnel)) => (f_cos * fresnel, pdf * fresnel),
+            None => (0.0, 0.0),
+        }
+    }
+
+    // refraction through a sampled microfacet alone, None on total internal reflection
+    pub fn sample_transmission(&self, wo: &Vec3, eta: f64) -> Option<Vec3> {
+        refract_local(wo, &self.sample_wm(wo), eta)
+    }
+
+    // the refracted part of dielectric_f_cos_pdf, with the pdf of sample_transmission
+    pub fn transmission_f_cos_pdf(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> (f64, f64) {
+        match self.dielectric_lobe(wo, wi, eta) {
+            Some((f_cos, pdf, fresnel)) if wi.z < 0.0 => (f_cos * fresnel, pdf),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    // reflection or refraction on the microfacet between wo and wi without the Fresnel
+    // factor, which is also the probability of sample_dielectric taking that branch
+    fn dielectric_lobe(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> Option<(f64, f64, f64)> {
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        let reflect = wi.z > 0.0;
+        let etap = if reflect { 1.0 } else { eta };
+        let wm = *wi * etap + *wo;
+        if wm.squared_length() == 0.0 {
+            return None;
+        }
+        let mut wm = wm.unit();
+        if wm.z < 0.0 {
+            wm = -wm;
+        }
+        // discard back facing microfacets
+        if (wm * *wi) * wi.z < 0.0 || (wm * *wo) * wo.z < 0.0 {
+            return None;
+        }
+
+        let r = fr_dielectric(*wo * wm, 1.0, eta);
+        let d = self.d(&wm);
+        let g = self.g(wo, wi);
+        let d_vis = self.d_visible(wo, &wm);
+        if reflect {
+            let f_cos = d * g / (4.0 * wo.z);
+            let pdf = d_vis / (4.0 * (*wo * wm).abs());
+            Some((f_cos, pdf, r))
+        } else {
+            let denom = (*wi * wm + (*wo * wm) / etap).powi(2);
+            let f_cos = d * g * ((*wi * wm) * (*wo * wm) / (wo.z * denom)).abs();
+            let pdf = d_vis * (*wi * wm).abs() / denom;
+            Some((f_cos, pdf, 1.0 - r))
+        }
+    }
+}
+
+// Fresnel reflectance of a conductor with complex IOR eta + i k
+fn fr_complex(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = clamp(cos_theta_i, 0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos2.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+pub fn fr_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    Color::new(
+        fr_complex(cos_theta_i, eta.x, k.x),
+        fr_complex(cos_theta_i, eta.y, k.y),
+        fr_complex(cos_theta_i, eta.z, k.z),
+    )
+}
+
+pub fn reflect_local(wo: &Vec3, n: &Vec3) -> Vec3 {
+    -*wo + *n * (2.0 * (*wo * *n))
+}
+
+// Refracts wo (pointing away from the surface, on the side n faces) with eta = eta_t / eta_i,
+// None on total internal reflection.
+pub fn refract_local(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = *wo * *n;
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*wo / eta + *n * (cos_i / eta - cos_t))
+}
+
+// local frame around the shading normal, which faces the incoming ray
+pub fn local_frame(r_in: &Ray, rec: &HitRecord) -> (ONB, Vec3) {
+    let frame = ONB::build_from_w(&rec.normal);
+    let wo = frame.to_local(&-r_in.dire.unit());
+    (frame, wo)
+}
+
+// Rough metal described by its complex index of refraction per channel.
+pub struct Conductor {
+    pub eta: Arc<dyn Texture>,
+    pub k: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>, // read from the first channel
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self::new_from_texture(
+            Arc::new(SolidColor::new(eta)),
+            Arc::new(SolidColor::new(k)),
+            scalar(roughness),
+        )
+    }
+
+    pub fn new_from_texture(
+        eta: Arc<dyn Texture>,
+        k: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+    ) -> Self {
+        Self { eta, k, roughness }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.143, 0.374, 1.442),
+            Color::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(
+            Color::new(0.200, 0.924, 1.102),
+            Color::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(
+            Color::new(1.657, 0.880, 0.521),
+            Color::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    fn distrib(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.value_at(rec).x)
+    }
+
+    fn fresnel(&self, rec: &HitRecord, cos_theta: f64) -> Color {
+        fr_conductor(cos_theta, &self.eta.value_at(rec), &self.k.value_at(rec))
+    }
+
+    fn f_cos(&self, rec: &HitRecord, distrib: &TrowbridgeReitz, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zero();
+        }
+        let wm = *wo + *wi;
+        if wm.squared_length() == 0.0 {
+            return Color::zero();
+        }
+        let wm = wm.unit();
+        self.fresnel(rec, *wo * wm) * (distrib.d(&wm) * distrib.g(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, distrib: &TrowbridgeReitz, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = *wo + *wi;
+        if wm.squared_length() == 0.0 {
+            return 0.0;
+        }
+        let wm = wm.unit();
+        distrib.d_visible(wo, &wm) / (4.0 * (*wo * wm).abs())
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z <= 0.0 {
+            return false;
+        }
+        let distrib = self.distrib(rec);
+        let wi = if distrib.smooth() {
+            *attenuation = self.fresnel(rec, wo.z);
+            Vec3::new(-wo.x, -wo.y, wo.z)
+        } else {
+            let wm = distrib.sample_wm(&wo);
+            let wi = reflect_local(&wo, &wm);
+            let pdf = self.pdf(&distrib, &wo, &wi);
+            if wi.z <= 0.0 || pdf <= 0.0 {
+                return false;
+            }
+            *attenuation = self.f_cos(rec, &distrib, &wo, &wi) / pdf;
+            wi
+        };
+        *scattered = Ray {
+            orig: rec.p,
+            dire: frame.local(&wi),
+            tm: r_in.tm,
+        };
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let distrib = self.distrib(rec);
+        if distrib.smooth() {
+            return Color::zero();
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        self.f_cos(rec, &distrib, &wo, &frame.to_local(&scattered.dire.unit()))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let distrib = self.distrib(rec);
+        if distrib.smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        self.pdf(&distrib, &wo, &frame.to_local(&scattered.dire.unit()))
+    }
+}
+
+// Frosted glass. Like Dielectric it takes part in the medium stack, so it can be nested.
+pub struct RoughDielectric {
+    pub ref_idx: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+}
+
+impl RoughDielectric {
+    pub fn new(ri: f64, roughness: f64) -> Self {
+        Self::new_from_texture(scalar(ri), scalar(roughness))
+    }
+
+    pub fn new_from_texture(ri: Arc<dyn Texture>, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            ref_idx: ri,
+            roughness,
+        }
+    }
+
+    fn ior(&self, rec: &HitRecord) -> f64 {
+        self.ref_idx.value_at(rec).x
+    }
+
+    fn distrib(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness.value_at(rec).x)
+    }
+
+    // IOR of the far side over the IOR of the side the ray comes from
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            self.ior(rec) / rec.outer_ior
+        } else {
+            rec.outer_ior / self.ior(rec)
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        let eta = self.eta(rec);
+        let distrib = self.distrib(rec);
+        let wi = match distrib.sample_dielectric(&wo, eta) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        if distrib.smooth() {
+            *attenuation = Color::ones();
+        } else {
+            let (f_cos, pdf) = distrib.dielectric_f_cos_pdf(&wo, &wi, eta);
+            if pdf <= 0.0 {
+                return false;
+            }
+            *attenuation = Color::ones() * (f_cos / pdf);
+        }
+        *scattered = Ray {
+            orig: rec.p,
+            dire: frame.local(&wi),
+            tm: r_in.tm,
+        };
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let distrib = self.distrib(rec);
+        if distrib.smooth() {
+            return Color::zero();
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        Color::ones() * distrib.dielectric_f_cos_pdf(&wo, &wi, self.eta(rec)).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let distrib = self.distrib(rec);
+        if distrib.smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        distrib.dielectric_f_cos_pdf(&wo, &wi, self.eta(rec)).1
+    }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        Some(MediumInterface {
+            ior: self.ior(rec),
+            priority: 0,
+            interior: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visible_normals_normalized() {
+        // the visible normal density integrates to one over the hemisphere
+        let distrib = TrowbridgeReitz::from_roughness(0.5);
+        let w = Vec3::new(0.6, 0.0, 0.8);
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = (i as f64 + 0.5) / n as f64;
+                let phi = (j as f64 + 0.5) / n as f64 * 2.0 * PI;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wm = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                integral += distrib.d_visible(&w, &wm) * 2.0 * PI / (n * n) as f64;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_conductor_fresnel() {
+        // a perfect conductor reflects everything, a real one reflects more at grazing angles
+        assert!((fr_complex(0.5, 1.0, 1e6) - 1.0).abs() < 1e-3);
+        let gold = Arc::new(Conductor::gold(0.0));
+        let rec = HitRecord::new(gold.clone());
+        let normal = gold.fresnel(&rec, 1.0);
+        let grazing = gold.fresnel(&rec, 0.05);
+        assert!(normal.x > normal.z && grazing.z > normal.z);
+    }
+
+    // one value for u < 0.5 and another above
+    struct Halves {
+        left: Color,
+        right: Color,
+    }
+
+    impl Texture for Halves {
+        fn value(&self, u: f64, _v: f64, _p: &Point) -> Color {
+            if u < 0.5 {
+                self.left
+            } else {
+                self.right
+            }
+        }
+    }
+
+    #[test]
+    fn test_textured_conductor() {
+        let halves = |left: f64, right: f64| -> Arc<dyn Texture> {
+            Arc::new(Halves {
+                left: Color::ones() * left,
+                right: Color::ones() * right,
+            })
+        };
+        let metal = Arc::new(Conductor::new_from_texture(
+            halves(0.2, 0.2),
+            halves(1.0, 4.0),
+            halves(0.3, 0.3),
+        ));
+        let mut rec = HitRecord::new(metal.clone());
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = true;
+        let r_in = Ray {
+            orig: Point::new(0.3, 0.0, 1.0),
+            dire: Vec3::new(-0.3, 0.0, -1.0),
+            tm: 0.0,
+        };
+        let scattered = Ray {
+            orig: Point::zero(),
+            dire: Vec3::new(-0.3, 0.0, 1.0),
+            tm: 0.0,
+        };
+        rec.u = 0.25;
+        let left = metal.eval(&r_in, &rec, &scattered);
+        rec.u = 0.75;
+        let right = metal.eval(&r_in, &rec, &scattered);
+        // a larger extinction coefficient reflects more
+        assert!(right.x > 1.2 * left.x, "{:?} {:?}", left, right);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::medium::MediumInterface;
+use crate::onb::*;
+use crate::ray::Ray;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// Tangent frame (t, b, n) around the outward normal, t along dpdu and b on the side of dpdv.
+// Primitives without tangents get an arbitrary one.
+fn tangent_frame(rec: &HitRecord) -> (Vec3, Vec3, Vec3) {
+    let n = if rec.front_face {
+        rec.normal
+    } else {
+        -rec.normal
+    };
+    let t = rec.dpdu - n * (rec.dpdu * n);
+    if t.squared_length() < 1e-16 {
+        let frame = ONB::build_from_w(&n);
+        return (frame.u, frame.v, n);
+    }
+    let t = t.unit();
+    let b = Vec3::cross(n, t);
+    if b * rec.dpdv < 0.0 {
+        (t, -b, n)
+    } else {
+        (t, b, n)
+    }
+}
+
+// copy of the hit with a new outward shading normal, turned to the side the ray came from
+fn with_normal(rec: &HitRecord, outward_normal: Vec3) -> HitRecord {
+    let mut shading = rec.clone();
+    let n = outward_normal.unit();
+    shading.normal = if rec.front_face { n } else { -n };
+    shading
+}
+
+// Perturbs the shading normal with a tangent space normal map (RGB in [0, 1] mapped to
+// [-1, 1], blue along the surface normal) and shades with the wrapped material. The
+// integrator keeps using the geometric normal to tell entering from leaving.
+pub struct NormalMap {
+    pub base: Arc<dyn Material>,
+    pub map: Arc<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(base: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self { base, map }
+    }
+
+    fn shading_record(&self, rec: &HitRecord) -> HitRecord {
+        let (t, b, n) = tangent_frame(rec);
+        let m = self.map.value_at(rec) * 2.0 - Vec3::ones();
+        let perturbed = t * m.x + b * m.y + n * m.z;
+        if perturbed.squared_length() < 1e-16 {
+            return rec.clone();
+        }
+        with_normal(rec, perturbed)
+    }
+}
+
+// Displaces the surface by scale * bump along the normal, as far as shading is concerned.
+// The slope comes from forward differences of the bump texture (first channel) in u and v,
+// du being the step in texture space.
+pub struct BumpMap {
+    pub base: Arc<dyn Material>,
+    pub bump: Arc<dyn Texture>,
+    pub scale: f64,
+    pub du: f64,
+}
+
+impl BumpMap {
+    pub fn new(base: Arc<dyn Material>, bump: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            base,
+            bump,
+            scale,
+            du: 0.0005,
+        }
+    }
+
+    fn shading_record(&self, rec: &HitRecord) -> HitRecord {
+        let (dpdu, dpdv, n) = if Vec3::cross(rec.dpdu, rec.dpdv).squared_length() > 0.0 {
+            let (_, _, n) = tangent_frame(rec);
+            (rec.dpdu, rec.dpdv, n)
+        } else {
+            tangent_frame(rec)
+        };
+        let height = |u: f64, v: f64, p: &Point| self.bump.value(u, v, p).x;
+        let h = height(rec.u, rec.v, &rec.p);
+        let h_u = height(rec.u + self.du, rec.v, &(rec.p + dpdu * self.du));
+        let h_v = height(rec.u, rec.v + self.du, &(rec.p + dpdv * self.du));
+
+        let dpdu = dpdu + n * (self.scale * (h_u - h) / self.du);
+        let dpdv = dpdv + n * (self.scale * (h_v - h) / self.du);
+        let bumped = Vec3::cross(dpdu, dpdv);
+        if bumped.squared_length() < 1e-16 {
+            return rec.clone();
+        }
+        // dpdu x dpdv can point inwards (e.g. on XZRect), keep the side of the surface
+        with_normal(rec, if bumped * n < 0.0 { -bumped } else { bumped })
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.base
+            .scatter(r_in, &self.shading_record(rec), attenuation, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.base.eval(r_in, &self.shading_record(rec), scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base
+            .scattering_pdf(r_in, &self.shading_record(rec), scattered)
+    }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        self.base.interface(rec)
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.base
+            .scatter(r_in, &self.shading_record(rec), attenuation, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.base.eval(r_in, &self.shading_record(rec), scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base
+            .scattering_pdf(r_in, &self.shading_record(rec), scattered)
+    }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        self.base.interface(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(front_face: bool) -> HitRecord {
+        let mut rec = HitRecord::new(Arc::new(Lambertian::new(Color::ones())));
+        rec.normal = Vec3::new(0.0, 0.0, if front_face { 1.0 } else { -1.0 });
+        rec.front_face = front_face;
+        rec.dpdu = Vec3::new(2.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 2.0, 0.0);
+        rec
+    }
+
+    #[test]
+    fn test_normal_map_tangent_space() {
+        let base: Arc<dyn Material> = Arc::new(Lambertian::new(Color::ones()));
+        let flat = NormalMap::new(
+            base.clone(),
+            Arc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0))),
+        );
+        let n = flat.shading_record(&record(true)).normal;
+        assert!((n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        // tilted towards +u, mirrored with the normal on the back face
+        let tilted = NormalMap::new(base, Arc::new(SolidColor::new(Color::new(1.0, 0.5, 1.0))));
+        let n = tilted.shading_record(&record(true)).normal;
+        assert!((n - Vec3::new(1.0, 0.0, 1.0).unit()).length() < 1e-9);
+        let n = tilted.shading_record(&record(false)).normal;
+        assert!((n + Vec3::new(1.0, 0.0, 1.0).unit()).length() < 1e-9);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::vec3::*;
+
+// orthonormal basis, w is the "up" axis of local shading coordinates
+pub struct ONB {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl ONB {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = n.unit();
+        let helper = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::cross(w, helper).unit();
+        let u = Vec3::cross(w, v);
+        Self { u, v, w }
+    }
+
+    // local to world
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
+
+    // world to local
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(*a * self.u, *a * self.v, *a * self.w)
+    }
+}
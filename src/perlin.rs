@@ -1,43 +1,91 @@
-use crate::rtweekend::*;
 use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 const POINT_COUNT: usize = 256;
 
+// Octaves of fractal noise: each one `lacunarity` times the frequency and `gain` times the
+// amplitude of the one before
+#[derive(Clone, Copy, Debug)]
+pub struct Octaves {
+    pub count: i32,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+
+impl Octaves {
+    pub fn new(count: i32) -> Self {
+        Self {
+            count,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+// Gradient noise over a 256 cell period, in 3D and in 4D for noise that changes over time.
+// The same seed gives the same noise.
 pub struct Perlin {
     ran_vec: [Vec3; POINT_COUNT],
+    ran_vec4: [[f64; 4]; POINT_COUNT],
     perm_x: [i32; POINT_COUNT],
     perm_y: [i32; POINT_COUNT],
     perm_z: [i32; POINT_COUNT],
+    perm_w: [i32; POINT_COUNT],
 }
 
 impl Perlin {
-    fn permute(tmp: &mut [i32; POINT_COUNT], n: i32) {
+    fn permute(tmp: &mut [i32; POINT_COUNT], n: i32, rng: &mut StdRng) {
         for k in 0..n - 1 {
             let i = (n - 1 - k) as usize;
-            let target = random_int(0, i as i32) as usize; //[0, i]
+            let target = rng.gen_range(0, i + 1); //[0, i]
             tmp.swap(i, target);
         }
     }
-    fn perlin_generate_perm() -> [i32; POINT_COUNT] {
+    fn perlin_generate_perm(rng: &mut StdRng) -> [i32; POINT_COUNT] {
         let mut tmp = [0 as i32; POINT_COUNT];
         #[allow(clippy::needless_range_loop)]
         for i in 0..POINT_COUNT {
             tmp[i] = i as i32;
         }
-        Perlin::permute(&mut tmp, POINT_COUNT as i32);
+        Perlin::permute(&mut tmp, POINT_COUNT as i32, rng);
 
         tmp
     }
+
+    // a different pattern every time
     pub fn new() -> Self {
+        Self::new_with_seed(rand::thread_rng().gen())
+    }
+
+    pub fn new_with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut tmp = [Vec3::zero(); POINT_COUNT];
         for i in tmp.iter_mut() {
-            *i = Vec3::random(-1.0, 1.0).unit();
+            *i = Vec3::new(
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+                rng.gen_range(-1.0, 1.0),
+            )
+            .unit();
+        }
+        let mut tmp4 = [[0.0; 4]; POINT_COUNT];
+        for g in tmp4.iter_mut() {
+            for x in g.iter_mut() {
+                *x = rng.gen_range(-1.0, 1.0);
+            }
+            let len = g.iter().map(|x| x * x).sum::<f64>().sqrt();
+            for x in g.iter_mut() {
+                *x /= len;
+            }
         }
         Self {
             ran_vec: tmp,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            ran_vec4: tmp4,
+            perm_x: Self::perlin_generate_perm(&mut rng),
+            perm_y: Self::perlin_generate_perm(&mut rng),
+            perm_z: Self::perlin_generate_perm(&mut rng),
+            perm_w: Self::perlin_generate_perm(&mut rng),
         }
     }
 
@@ -88,18 +136,110 @@ impl Perlin {
         Self::perlin_interp(&c_array, uu, vv, ww)
     }
 
-    pub fn turb(&self, p: &Point, depth: i32) -> f64 {
+    // 4D noise, w usually being time
+    pub fn noise4(&self, p: &Point, w: f64) -> f64 {
+        let x = [p.x, p.y, p.z, w];
+        let mut cell = [0; 4];
+        let mut frac = [0.0; 4];
+        let mut smooth = [0.0; 4];
+        for a in 0..4 {
+            cell[a] = x[a].floor() as i32;
+            frac[a] = x[a] - x[a].floor();
+            smooth[a] = frac[a] * frac[a] * (3.0 - 2.0 * frac[a]);
+        }
+        let perms = [&self.perm_x, &self.perm_y, &self.perm_z, &self.perm_w];
+
         let mut accum = 0.0;
-        let mut temp_p = *p;
+        for corner in 0..16 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for a in 0..4 {
+                let bit = (corner >> a) & 1;
+                index ^= perms[a][((cell[a] + bit) & 255) as usize];
+                weight *= if bit == 1 { smooth[a] } else { 1.0 - smooth[a] };
+            }
+            let g = &self.ran_vec4[index as usize];
+            let mut dot = 0.0;
+            for a in 0..4 {
+                dot += g[a] * (frac[a] - ((corner >> a) & 1) as f64);
+            }
+            accum += weight * dot;
+        }
+        accum
+    }
+
+    // sum over octaves of `f(frequency)` times the amplitude, and the sum of amplitudes
+    fn octave_sum(octaves: &Octaves, f: impl Fn(f64) -> f64) -> (f64, f64) {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = 1.0;
         let mut weight = 1.0;
 
-        for _i in 0..depth {
-            accum += weight * self.noise(&temp_p);
-            weight *= 0.5;
-            temp_p *= 2.0;
+        for _i in 0..octaves.count {
+            accum += weight * f(frequency);
+            total += weight;
+            weight *= octaves.gain;
+            frequency *= octaves.lacunarity;
         }
 
-        accum.abs()
+        (accum, total)
+    }
+
+    // Perlin's turbulence: the absolute value of the octave sum, not normalized
+    pub fn turb(&self, p: &Point, octaves: &Octaves) -> f64 {
+        Self::octave_sum(octaves, |f| self.noise(&(*p * f))).0.abs()
+    }
+
+    pub fn turb4(&self, p: &Point, w: f64, octaves: &Octaves) -> f64 {
+        Self::octave_sum(octaves, |f| self.noise4(&(*p * f), w * f))
+            .0
+            .abs()
+    }
+
+    // fractional Brownian motion, the octave sum divided by the sum of amplitudes, roughly
+    // in [-1, 1]
+    pub fn fbm(&self, p: &Point, octaves: &Octaves) -> f64 {
+        let (accum, total) = Self::octave_sum(octaves, |f| self.noise(&(*p * f)));
+        if total > 0.0 {
+            accum / total
+        } else {
+            0.0
+        }
+    }
+
+    pub fn fbm4(&self, p: &Point, w: f64, octaves: &Octaves) -> f64 {
+        let (accum, total) = Self::octave_sum(octaves, |f| self.noise4(&(*p * f), w * f));
+        if total > 0.0 {
+            accum / total
+        } else {
+            0.0
+        }
+    }
+
+    // ridged multifractal (Musgrave): sharp crests where the noise crosses zero, each octave
+    // weighted by the one before so that detail gathers on the ridges, in [0, 1]
+    pub fn ridged(&self, p: &Point, octaves: &Octaves) -> f64 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut weight = 1.0;
+        let mut prev = 1.0;
+
+        for _i in 0..octaves.count {
+            let n = 1.0 - self.noise(&(*p * frequency)).abs();
+            let n = n * n;
+            accum += weight * n * prev;
+            total += weight;
+            prev = n;
+            weight *= octaves.gain;
+            frequency *= octaves.lacunarity;
+        }
+
+        if total > 0.0 {
+            accum / total
+        } else {
+            0.0
+        }
     }
 }
 
@@ -108,3 +248,23 @@ impl Default for Perlin {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_noise() {
+        let a = Perlin::new_with_seed(1);
+        let b = Perlin::new_with_seed(1);
+        let c = Perlin::new_with_seed(2);
+        let p = Point::new(0.3, 1.7, -2.2);
+        assert!((a.noise(&p) - b.noise(&p)).abs() < 1e-12);
+        assert!((a.noise4(&p, 0.6) - b.noise4(&p, 0.6)).abs() < 1e-12);
+        assert!((a.noise(&p) - c.noise(&p)).abs() > 1e-9);
+
+        // gradient noise vanishes on the lattice and changes smoothly in time
+        assert!(a.noise4(&Point::new(1.0, 2.0, 3.0), 4.0).abs() < 1e-12);
+        assert!((a.noise4(&p, 0.6) - a.noise4(&p, 0.6001)).abs() < 1e-3);
+    }
+}
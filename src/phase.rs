@@ -0,0 +1,141 @@
+use crate::onb::*;
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Scattering inside a volume. Both directions are unit vectors along the ray, so cos_theta = 1
+// means the ray keeps going straight on.
+pub trait PhaseFunction: Send + Sync {
+    fn p(&self, dir_in: &Vec3, dir_out: &Vec3) -> f64;
+    fn sample(&self, dir_in: &Vec3) -> Vec3;
+}
+
+// direction at angle acos(cos_theta) to `axis`, uniform in azimuth
+fn direction_around(axis: &Vec3, cos_theta: f64) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = random_double(0.0, 2.0 * PI);
+    ONB::build_from_w(axis).local(&Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+fn hg(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.max(1e-12).sqrt())
+}
+
+fn sample_hg_cos(g: f64) -> f64 {
+    let xi = random_double(0.0, 1.0);
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * xi;
+    }
+    let sqr = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+    clamp((1.0 + g * g - sqr * sqr) / (2.0 * g), -1.0, 1.0)
+}
+
+pub struct Isotropic {}
+
+impl Isotropic {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for Isotropic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PhaseFunction for Isotropic {
+    fn p(&self, _dir_in: &Vec3, _dir_out: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample(&self, _dir_in: &Vec3) -> Vec3 {
+        random_unit_vector()
+    }
+}
+
+// g in (-1, 1): positive scatters forward (haze, clouds), negative backward
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self { g }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, dir_in: &Vec3, dir_out: &Vec3) -> f64 {
+        hg(*dir_in * *dir_out, self.g)
+    }
+
+    fn sample(&self, dir_in: &Vec3) -> Vec3 {
+        direction_around(dir_in, sample_hg_cos(self.g))
+    }
+}
+
+// weight * HG(g1) + (1 - weight) * HG(g2), e.g. a strong forward lobe with some back scattering
+pub struct DoubleHenyeyGreenstein {
+    pub g1: f64,
+    pub g2: f64,
+    pub weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self { g1, g2, weight }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn p(&self, dir_in: &Vec3, dir_out: &Vec3) -> f64 {
+        let cos_theta = *dir_in * *dir_out;
+        self.weight * hg(cos_theta, self.g1) + (1.0 - self.weight) * hg(cos_theta, self.g2)
+    }
+
+    fn sample(&self, dir_in: &Vec3) -> Vec3 {
+        let g = if random_double(0.0, 1.0) < self.weight {
+            self.g1
+        } else {
+            self.g2
+        };
+        direction_around(dir_in, sample_hg_cos(g))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hg_normalized() {
+        let n = 2000;
+        for g in [-0.7, 0.0, 0.3, 0.9].iter() {
+            // integrate over the sphere in cos_theta
+            let mut integral = 0.0;
+            for i in 0..n {
+                let cos_theta = -1.0 + (i as f64 + 0.5) * 2.0 / n as f64;
+                integral += hg(cos_theta, *g) * 2.0 * PI * 2.0 / n as f64;
+            }
+            assert!((integral - 1.0).abs() < 1e-2, "g = {}: {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn test_hg_sample_mean_cosine() {
+        // the mean cosine of Henyey-Greenstein is g
+        let phase = HenyeyGreenstein::new(0.6);
+        let dir_in = Vec3::new(1.0, 2.0, -0.5).unit();
+        let n = 20000;
+        let mut mean = 0.0;
+        for _i in 0..n {
+            mean += phase.sample(&dir_in) * dir_in / n as f64;
+        }
+        assert!((mean - 0.6).abs() < 0.02);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::medium::*;
+use crate::microfacet::*;
+use crate::ray::Ray;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// smallest alpha of the specular lobes, keeps them out of the delta case
+const MIN_ALPHA: f64 = 1e-3;
+
+// Disney-style principled BSDF (Burley 2012/2015). Every parameter is a texture, scalar
+// parameters read the first channel. Layers: diffuse with retro-reflection and a subsurface
+// approximation plus sheen, GGX specular reflection, glass-like transmission, clearcoat.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>, // 0.5 is a dielectric with F0 = 0.04
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub subsurface: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+}
+
+impl Principled {
+    // a plain rough dielectric, set the other fields to taste
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            specular: scalar(0.5),
+            specular_tint: scalar(0.0),
+            sheen: scalar(0.0),
+            sheen_tint: scalar(0.5),
+            clearcoat: scalar(0.0),
+            clearcoat_gloss: scalar(1.0),
+            transmission: scalar(0.0),
+            subsurface: scalar(0.0),
+            ior: scalar(1.5),
+        }
+    }
+
+    pub fn new_from_color(c: Color) -> Self {
+        Self::new(Arc::new(SolidColor::new(c)))
+    }
+
+    fn params(&self, rec: &HitRecord) -> Params {
+        let value = |t: &Arc<dyn Texture>| t.value_at(rec);
+        let scalar = |t: &Arc<dyn Texture>| clamp(value(t).x, 0.0, 1.0);
+        let base = value(&self.base_color);
+        let lum = luminance(&base);
+        let tint = if lum > 0.0 { base / lum } else { Color::ones() };
+        let metallic = scalar(&self.metallic);
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_f0 = (Color::ones() * (1.0 - specular_tint) + tint * specular_tint)
+            * (0.08 * scalar(&self.specular));
+        let sheen_tint = scalar(&self.sheen_tint);
+        let roughness = scalar(&self.roughness);
+        let alpha = (roughness * roughness).max(MIN_ALPHA);
+        let transmission = scalar(&self.transmission);
+        let ior = value(&self.ior).x;
+
+        Params {
+            base,
+            roughness,
+            f0: dielectric_f0 * (1.0 - metallic) + base * metallic,
+            sheen: (Color::ones() * (1.0 - sheen_tint) + tint * sheen_tint) * scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_alpha: 0.1 * (1.0 - scalar(&self.clearcoat_gloss))
+                + 0.001 * scalar(&self.clearcoat_gloss),
+            subsurface: scalar(&self.subsurface),
+            distrib: TrowbridgeReitz::new(alpha, alpha),
+            eta: if rec.front_face {
+                ior / rec.outer_ior
+            } else {
+                rec.outer_ior / ior
+            },
+            weights: [
+                (1.0 - metallic) * (1.0 - transmission),
+                1.0,
+                (1.0 - metallic) * transmission,
+                0.25 * scalar(&self.clearcoat),
+            ],
+        }
+    }
+}
+
+struct Params {
+    base: Color,
+    roughness: f64,
+    f0: Color,
+    sheen: Color,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    subsurface: f64,
+    distrib: TrowbridgeReitz,
+    eta: f64,
+    weights: [f64; 4], // diffuse, specular, transmission, clearcoat
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const TRANSMISSION: usize = 2;
+const CLEARCOAT: usize = 3;
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - clamp(cosine, 0.0, 1.0)).powi(5)
+}
+
+// GTR1, the long tailed distribution of the clearcoat
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+fn sample_gtr1(alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos2 = (1.0 - a2.powf(1.0 - random_double(0.0, 1.0))) / (1.0 - a2);
+    let cos_theta = cos2.max(0.0).sqrt();
+    let sin_theta = (1.0 - cos2).max(0.0).sqrt();
+    let phi = random_double(0.0, 2.0 * PI);
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+impl Params {
+    // f * |cos theta_i| of every lobe, for local directions
+    fn f_cos_pdf(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        let total: f64 = self.weights.iter().sum();
+        let mut f = Color::zero();
+        let mut pdf = 0.0;
+
+        // refraction only, the specular lobe reflects for the glass as well
+        if self.weights[TRANSMISSION] > 0.0 {
+            let (glass_f, glass_pdf) = self.distrib.transmission_f_cos_pdf(wo, wi, self.eta);
+            f += self.base * (glass_f * self.weights[TRANSMISSION]);
+            pdf += glass_pdf * self.weights[TRANSMISSION] / total;
+        }
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return (f, pdf);
+        }
+
+        let wh = (*wo + *wi).unit();
+        let cos_d = *wi * wh;
+        let (cos_i, cos_o) = (wi.z, wo.z);
+
+        if self.weights[DIFFUSE] > 0.0 {
+            let fl = schlick_weight(cos_i);
+            let fv = schlick_weight(cos_o);
+            let rr = 2.0 * self.roughness * cos_d * cos_d;
+            let lambert = (1.0 - 0.5 * fl) * (1.0 - 0.5 * fv);
+            let retro = rr * (fl + fv + fl * fv * (rr - 1.0));
+            let fss90 = rr / 2.0;
+            let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+            let ss = 1.25 * (fss * (1.0 / (cos_i + cos_o) - 0.5) + 0.5);
+            let diffuse = self.base / PI
+                * ((lambert + retro) * (1.0 - self.subsurface) + ss * self.subsurface);
+            let sheen = self.sheen * schlick_weight(cos_d);
+            f += (diffuse + sheen) * (self.weights[DIFFUSE] * cos_i);
+            pdf += cos_i / PI * self.weights[DIFFUSE] / total;
+        }
+
+        let d = self.distrib.d(&wh);
+        let fresnel = self.f0 + (Color::ones() - self.f0) * schlick_weight(*wo * wh);
+        f += fresnel * (d * self.distrib.g(wo, wi) / (4.0 * cos_o) * self.weights[SPECULAR]);
+        pdf += self.distrib.d_visible(wo, &wh) / (4.0 * (*wo * wh).abs()) * self.weights[SPECULAR]
+            / total;
+
+        if self.clearcoat > 0.0 {
+            let dr = gtr1(wh.z, self.clearcoat_alpha);
+            let fr = 0.04 + 0.96 * schlick_weight(cos_d);
+            let gr = TrowbridgeReitz::new(0.25, 0.25).g(wo, wi);
+            f += Color::ones() * (0.25 * self.clearcoat * dr * fr * gr / (4.0 * cos_o));
+            pdf += dr * wh.z / (4.0 * (*wo * wh).abs()) * self.weights[CLEARCOAT] / total;
+        }
+        (f, pdf)
+    }
+
+    fn sample_wi(&self, wo: &Vec3) -> Option<Vec3> {
+        let total: f64 = self.weights.iter().sum();
+        let mut u = random_double(0.0, total);
+        let mut lobe = 0;
+        while lobe < CLEARCOAT && u >= self.weights[lobe] {
+            u -= self.weights[lobe];
+            lobe += 1;
+        }
+        let wi = match lobe {
+            DIFFUSE => random_cosine_direction(),
+            SPECULAR => reflect_local(wo, &self.distrib.sample_wm(wo)),
+            TRANSMISSION => return self.distrib.sample_transmission(wo, self.eta),
+            _ => reflect_local(wo, &sample_gtr1(self.clearcoat_alpha)),
+        };
+        // reflections that end up below the surface are lost, the pdf has no share for them
+        if wi.z > 0.0 {
+            Some(wi)
+        } else {
+            None
+        }
+    }
+}
+
+// cosine weighted direction around +z
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double(0.0, 1.0);
+    let phi = random_double(0.0, 2.0 * PI);
+    let r = r1.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r1).sqrt())
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let params = self.params(rec);
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z <= 0.0 {
+            return false;
+        }
+        let wi = match params.sample_wi(&wo) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        let (f, pdf) = params.f_cos_pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+        *attenuation = f / pdf;
+        *scattered = Ray {
+            orig: rec.p,
+            dire: frame.local(&wi),
+            tm: r_in.tm,
+        };
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        self.params(rec).f_cos_pdf(&wo, &wi).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&scattered.dire.unit());
+        self.params(rec).f_cos_pdf(&wo, &wi).1
+    }
+
+    // transmissive principled surfaces refract like glass, so they take part in nesting
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        if self.params(rec).weights[TRANSMISSION] <= 0.0 {
+            return None;
+        }
+        Some(MediumInterface {
+            ior: self.ior.value_at(rec).x,
+            priority: 0,
+            interior: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit_record(mat: Arc<dyn Material>) -> HitRecord {
+        let mut rec = HitRecord::new(mat);
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_face = true;
+        rec
+    }
+
+    #[test]
+    fn test_opaque_not_an_interface() {
+        // an opaque object in water must not be taken for part of the water's volume
+        let water = Dielectric::new_nested(1.33, 1, None);
+        let opaque = Arc::new(Principled::new_from_color(Color::new(0.8, 0.2, 0.2)));
+        let rec = hit_record(opaque.clone());
+        let mut stack = MediumStack::new();
+        stack.cross(1, &water.interface(&rec).unwrap(), true);
+        assert!(opaque.interface(&rec).is_none());
+
+        let mut glass = Principled::new_from_color(Color::ones());
+        glass.transmission = scalar(1.0);
+        let interface = glass.interface(&rec).unwrap();
+        assert_eq!(stack.outer_ior(2, &interface, true), None);
+    }
+
+    #[test]
+    fn test_textured_ior() {
+        let mut glass = Principled::new_from_color(Color::ones());
+        glass.transmission = scalar(1.0);
+        glass.ior = Arc::new(CheckerTexture::new_with_frequency(
+            Color::ones() * 1.3,
+            Color::ones() * 1.8,
+            PI,
+        ));
+        let glass = Arc::new(glass);
+        let mut rec = hit_record(glass.clone());
+        let ior = |rec: &HitRecord| glass.interface(rec).unwrap().ior;
+        rec.p = Point::new(0.5, 0.5, 0.5);
+        let first = ior(&rec);
+        rec.p = Point::new(-0.5, 0.5, 0.5);
+        let second = ior(&rec);
+        assert!((first - second).abs() > 0.4, "{} {}", first, second);
+    }
+
+    #[test]
+    fn test_sample_matches_pdf() {
+        let mut mat = Principled::new_from_color(Color::new(0.7, 0.5, 0.3));
+        mat.metallic = scalar(0.3);
+        mat.transmission = scalar(0.5);
+        mat.clearcoat = scalar(0.5);
+        mat.clearcoat_gloss = scalar(0.0);
+        mat.sheen = scalar(0.5);
+        let mat = Arc::new(mat);
+        let params = mat.params(&hit_record(mat.clone()));
+        let wo = Vec3::new(0.4, 0.1, 0.9).unit();
+
+        // share of the samples taken, and of those going below the surface, against the
+        // integral of the pdf
+        let samples = 100000;
+        let (mut below, mut taken) = (0, 0);
+        for _ in 0..samples {
+            if let Some(wi) = params.sample_wi(&wo) {
+                let (f, pdf) = params.f_cos_pdf(&wo, &wi);
+                assert!(f.x >= 0.0 && f.y >= 0.0 && f.z >= 0.0 && pdf > 0.0);
+                taken += 1;
+                if wi.z < 0.0 {
+                    below += 1;
+                }
+            }
+        }
+        let (mut below_pdf, mut total_pdf) = (0.0, 0.0);
+        let n = 500;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                let (f, pdf) = params.f_cos_pdf(&wo, &wi);
+                assert!(f.x >= 0.0 && f.y >= 0.0 && f.z >= 0.0 && pdf >= 0.0);
+                total_pdf += pdf * 4.0 * PI / (n * n) as f64;
+                if wi.z < 0.0 {
+                    below_pdf += pdf * 4.0 * PI / (n * n) as f64;
+                }
+            }
+        }
+        let below = below as f64 / samples as f64;
+        let taken = taken as f64 / samples as f64;
+        assert!((below - below_pdf).abs() < 0.01, "{} {}", below, below_pdf);
+        assert!((taken - total_pdf).abs() < 0.01, "{} {}", taken, total_pdf);
+    }
+}
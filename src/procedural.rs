@@ -0,0 +1,505 @@
+use crate::hittable::HitRecord;
+use crate::perlin::*;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// Procedural patterns. Two-tone patterns take their tones as textures, the noise textures
+// return grey values in [0, 1] to be colored or used as scalar parameters.
+
+fn mix(a: Color, b: Color, t: f64) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+// hash of a lattice cell, for feature points and per-cell variation
+fn hash(x: i64, y: i64, z: i64, seed: u64) -> u64 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+// three values in [0, 1) from a hash
+fn hash_point(h: u64) -> Vec3 {
+    let unit = |bits: u64| (bits & 0x1f_ffff) as f64 / (1 << 21) as f64;
+    Vec3::new(unit(h), unit(h >> 21), unit(h >> 42))
+}
+
+// distances to the closest and second closest of one random feature point per unit cell
+// (Worley 1996)
+pub fn worley(p: &Point, seed: u64) -> (f64, f64) {
+    let (cx, cy, cz) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let mut f1 = INFINITY;
+    let mut f2 = INFINITY;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                let feature =
+                    Vec3::new(x as f64, y as f64, z as f64) + hash_point(hash(x, y, z, seed));
+                let distance = (feature - *p).length();
+                if distance < f1 {
+                    f2 = f1;
+                    f1 = distance;
+                } else if distance < f2 {
+                    f2 = distance;
+                }
+            }
+        }
+    }
+    (f1, f2)
+}
+
+// checker over the texture coordinates, u_count by v_count squares
+pub struct UvCheckerTexture {
+    pub odd: Arc<dyn Texture>,
+    pub even: Arc<dyn Texture>,
+    pub u_count: f64,
+    pub v_count: f64,
+}
+
+impl UvCheckerTexture {
+    pub fn new(c1: Color, c2: Color, u_count: f64, v_count: f64) -> Self {
+        Self {
+            even: Arc::new(SolidColor::new(c1)),
+            odd: Arc::new(SolidColor::new(c2)),
+            u_count,
+            v_count,
+        }
+    }
+
+    fn is_even(&self, u: f64, v: f64) -> bool {
+        let i = (u * self.u_count).floor() as i64 + (v * self.v_count).floor() as i64;
+        i.rem_euclid(2) == 0
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        if self.is_even(u, v) {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.is_even(rec.u, rec.v) {
+            self.even.value_at(rec)
+        } else {
+            self.odd.value_at(rec)
+        }
+    }
+}
+
+// parallel bands across `axis`, `frequency` pairs of stripes per unit, the first tone
+// covering `ratio` of each pair
+pub struct StripeTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub axis: Vec3,
+    pub frequency: f64,
+    pub ratio: f64,
+}
+
+impl StripeTexture {
+    pub fn new(c1: Color, c2: Color, axis: Vec3, frequency: f64) -> Self {
+        Self {
+            a: Arc::new(SolidColor::new(c1)),
+            b: Arc::new(SolidColor::new(c2)),
+            axis: axis.unit(),
+            frequency,
+            ratio: 0.5,
+        }
+    }
+
+    fn is_first(&self, p: &Point) -> bool {
+        let x = (*p * self.axis) * self.frequency;
+        x - x.floor() < self.ratio
+    }
+}
+
+impl Texture for StripeTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        if self.is_first(p) {
+            self.a.value(u, v, p)
+        } else {
+            self.b.value(u, v, p)
+        }
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.is_first(&rec.p) {
+            self.a.value_at(rec)
+        } else {
+            self.b.value_at(rec)
+        }
+    }
+}
+
+// linear blend between two colors from `start` to `end`, constant beyond them
+pub struct GradientTexture {
+    pub from: Color,
+    pub to: Color,
+    pub start: Point,
+    pub end: Point,
+}
+
+impl GradientTexture {
+    pub fn new(from: Color, to: Color, start: Point, end: Point) -> Self {
+        Self {
+            from,
+            to,
+            start,
+            end,
+        }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let d = self.end - self.start;
+        let t = (*p - self.start) * d / d.squared_length();
+        mix(self.from, self.to, clamp(t, 0.0, 1.0))
+    }
+}
+
+// running bond over the texture coordinates: rows of bricks shifted by half a brick, sizes
+// in (u, v), each brick a little lighter or darker by up to `variation`
+pub struct BrickTexture {
+    pub brick: Arc<dyn Texture>,
+    pub mortar: Arc<dyn Texture>,
+    pub brick_width: f64,
+    pub brick_height: f64,
+    pub mortar_width: f64,
+    pub variation: f64,
+}
+
+impl BrickTexture {
+    pub fn new(
+        brick: Color,
+        mortar: Color,
+        brick_width: f64,
+        brick_height: f64,
+        mortar_width: f64,
+    ) -> Self {
+        Self {
+            brick: Arc::new(SolidColor::new(brick)),
+            mortar: Arc::new(SolidColor::new(mortar)),
+            brick_width,
+            brick_height,
+            mortar_width,
+            variation: 0.15,
+        }
+    }
+
+    // brightness of the brick at (u, v), None in the mortar
+    fn shade(&self, u: f64, v: f64) -> Option<f64> {
+        // position in units of bricks, every other row shifted by half a brick
+        let by = v / self.brick_height;
+        let row = by.floor();
+        let bx = u / self.brick_width + if row as i64 % 2 == 0 { 0.0 } else { 0.5 };
+        let col = bx.floor();
+
+        let half = 0.5 * self.mortar_width;
+        let (fx, fy) = (
+            (bx - col) * self.brick_width,
+            (by - row) * self.brick_height,
+        );
+        if fx < half || fx > self.brick_width - half || fy < half || fy > self.brick_height - half {
+            return None;
+        }
+        let shade = hash_point(hash(col as i64, row as i64, 0, 0)).x * 2.0 - 1.0;
+        Some(1.0 + self.variation * shade)
+    }
+}
+
+impl Texture for BrickTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        match self.shade(u, v) {
+            Some(shade) => self.brick.value(u, v, p) * shade,
+            None => self.mortar.value(u, v, p),
+        }
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        match self.shade(rec.u, rec.v) {
+            Some(shade) => self.brick.value_at(rec) * shade,
+            None => self.mortar.value_at(rec),
+        }
+    }
+}
+
+// veins from turbulence added to a sine along z, the classic Perlin marble
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub base: Color,
+    pub vein: Color,
+    pub scale: f64,
+    pub turbulence: f64,
+    pub octaves: Octaves,
+}
+
+impl MarbleTexture {
+    pub fn new(base: Color, vein: Color, scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            base,
+            vein,
+            scale,
+            turbulence: 10.0,
+            octaves: Octaves::new(7),
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let turb = self.noise.turb(&(*p * self.scale), &self.octaves);
+        let t = 0.5 * (1.0 + (self.scale * p.z + self.turbulence * turb).sin());
+        mix(self.vein, self.base, t)
+    }
+}
+
+// growth rings around the y axis, `scale` rings per unit, wobbled by noise
+pub struct WoodTexture {
+    pub noise: Perlin,
+    pub light: Color,
+    pub dark: Color,
+    pub scale: f64,
+    pub turbulence: f64,
+}
+
+impl WoodTexture {
+    pub fn new(light: Color, dark: Color, scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            light,
+            dark,
+            scale,
+            turbulence: 0.3,
+        }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let r = (p.x * p.x + p.z * p.z).sqrt() * self.scale;
+        // stretched along the trunk like grain
+        let q = Point::new(p.x * 4.0, p.y * 0.5, p.z * 4.0);
+        let ring = r + self.turbulence * self.noise.fbm(&q, &Octaves::new(4));
+        let t = ring - ring.floor();
+        // late wood at the end of each ring is darker
+        mix(self.light, self.dark, t * t * t)
+    }
+}
+
+// fractional Brownian motion noise, changing over the ray time with a speed
+pub struct FbmTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: Octaves,
+    pub speed: f64,
+}
+
+impl FbmTexture {
+    pub fn new(scale: f64, octaves: i32) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves: Octaves::new(octaves),
+            speed: 0.0,
+        }
+    }
+}
+
+impl Texture for FbmTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let n = self.noise.fbm(&(*p * self.scale), &self.octaves);
+        Color::ones() * clamp(0.5 * (1.0 + n), 0.0, 1.0)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.speed == 0.0 {
+            return self.value(rec.u, rec.v, &rec.p);
+        }
+        let p = rec.p * self.scale;
+        let n = self.noise.fbm4(&p, rec.time * self.speed, &self.octaves);
+        Color::ones() * clamp(0.5 * (1.0 + n), 0.0, 1.0)
+    }
+}
+
+// turbulence, the absolute octave sum: billowy clouds and fire, changing over the ray time
+// with a speed
+pub struct TurbulenceTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: Octaves,
+    pub speed: f64,
+}
+
+impl TurbulenceTexture {
+    pub fn new(scale: f64, octaves: i32) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves: Octaves::new(octaves),
+            speed: 0.0,
+        }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let n = self.noise.turb(&(*p * self.scale), &self.octaves);
+        Color::ones() * clamp(n, 0.0, 1.0)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.speed == 0.0 {
+            return self.value(rec.u, rec.v, &rec.p);
+        }
+        let p = rec.p * self.scale;
+        let n = self.noise.turb4(&p, rec.time * self.speed, &self.octaves);
+        Color::ones() * clamp(n, 0.0, 1.0)
+    }
+}
+
+// ridged multifractal noise, mountain ranges and lightning-like veins
+pub struct RidgedTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: Octaves,
+}
+
+impl RidgedTexture {
+    pub fn new(scale: f64, octaves: i32) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves: Octaves::new(octaves),
+        }
+    }
+}
+
+impl Texture for RidgedTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        Color::ones() * self.noise.ridged(&(*p * self.scale), &self.octaves)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CellularFeature {
+    F1,    // distance to the closest point: round cells, dark in the middle
+    F2,    // distance to the second closest
+    Edges, // F2 - F1: dark lines where cells meet, cracks and scales
+}
+
+// cellular (Worley) noise, `scale` cells per unit
+pub struct WorleyTexture {
+    pub scale: f64,
+    pub feature: CellularFeature,
+    pub seed: u64,
+}
+
+impl WorleyTexture {
+    pub fn new(scale: f64, feature: CellularFeature) -> Self {
+        Self {
+            scale,
+            feature,
+            seed: 0,
+        }
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let (f1, f2) = worley(&(*p * self.scale), self.seed);
+        let x = match self.feature {
+            CellularFeature::F1 => f1,
+            CellularFeature::F2 => f2,
+            CellularFeature::Edges => f2 - f1,
+        };
+        Color::ones() * clamp(x, 0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worley() {
+        for i in 0..100 {
+            let p = Point::new(i as f64 * 0.37, -(i as f64) * 0.11, i as f64 * 0.05);
+            let (f1, f2) = worley(&p, 7);
+            assert!(f1 <= f2 && f1 < 3f64.sqrt());
+            // the same seed gives the same cells
+            assert_eq!(worley(&p, 7), (f1, f2));
+        }
+        // a feature point is where the distance vanishes
+        let cell = hash_point(hash(2, 3, 4, 7)) + Vec3::new(2.0, 3.0, 4.0);
+        assert!(worley(&cell, 7).0 < 1e-12);
+    }
+
+    #[test]
+    fn test_bricks() {
+        let bricks = BrickTexture {
+            variation: 0.0,
+            ..BrickTexture::new(Color::ones(), Color::zero(), 0.25, 0.1, 0.02)
+        };
+        let p = Point::zero();
+        // middle of a brick, its edges, and the joint shifted by half a brick a row up
+        assert_eq!(bricks.value(0.125, 0.05, &p), Color::ones());
+        assert_eq!(bricks.value(0.249, 0.05, &p), Color::zero());
+        assert_eq!(bricks.value(0.125, 0.099, &p), Color::zero());
+        assert_eq!(bricks.value(0.125, 0.15, &p), Color::zero());
+        assert_eq!(bricks.value(0.25, 0.15, &p), Color::ones());
+    }
+
+    // zero through value, the normal through value_at
+    struct Normal {}
+    impl Texture for Normal {
+        fn value(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+            Color::zero()
+        }
+        fn value_at(&self, rec: &HitRecord) -> Color {
+            rec.normal
+        }
+    }
+
+    #[test]
+    fn test_value_at_reaches_inputs() {
+        let normal: Arc<dyn Texture> = Arc::new(Normal {});
+        let mut rec = HitRecord::new(Arc::new(crate::material::Lambertian::new(Color::ones())));
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        rec.u = 0.125;
+        rec.v = 0.05;
+
+        let checker = UvCheckerTexture {
+            even: normal.clone(),
+            odd: normal.clone(),
+            ..UvCheckerTexture::new(Color::zero(), Color::zero(), 4.0, 4.0)
+        };
+        let stripes = StripeTexture {
+            a: normal.clone(),
+            b: normal.clone(),
+            ..StripeTexture::new(Color::zero(), Color::zero(), Vec3::new(1.0, 0.0, 0.0), 2.0)
+        };
+        let bricks = BrickTexture {
+            brick: normal.clone(),
+            mortar: normal,
+            variation: 0.0,
+            ..BrickTexture::new(Color::zero(), Color::zero(), 0.25, 0.1, 0.02)
+        };
+        for texture in [&checker as &dyn Texture, &stripes, &bricks].iter() {
+            assert_eq!(texture.value_at(&rec), rec.normal);
+        }
+    }
+}
@@ -11,3 +11,11 @@ impl Ray {
         self.orig + self.dire * t
     }
 }
+
+// Rays through the neighbouring pixels, one step right and one up, for estimating how
+// much of a texture a pixel covers
+#[derive(Clone, Debug, PartialEq)]
+pub struct RayDifferential {
+    pub rx: Ray,
+    pub ry: Ray,
+}
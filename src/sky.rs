@@ -0,0 +1,232 @@
+use crate::background::*;
+use crate::material::luminance;
+use crate::onb::ONB;
+use crate::rtweekend::*;
+use crate::spectrum::xyz_to_linear_srgb;
+use crate::vec3::*;
+
+// extraterrestrial illuminance of the sun in klx, spread over the disc whatever its size
+const SUN_ILLUMINANCE: f64 = 128.0;
+
+// share of the samples aimed at the sun, the rest follow the brightness of the sky
+const SUN_PROBABILITY: f64 = 0.5;
+
+// coefficients A to E of Perez et al.'s sky distribution
+type Perez = [f64; 5];
+
+fn perez(c: &Perez, cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+fn cubic(c: [f64; 4], x: f64) -> f64 {
+    ((c[0] * x + c[1]) * x + c[2]) * x + c[3]
+}
+
+// Daylight from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight"
+// (1999): the clear sky for a sun position and turbidity (2 is very clear, 10 hazy), and
+// the sun as a disc reddened by the air it shines through. Below the horizon is a diffuse
+// ground lit by both. Radiance is in kcd/m^2 times `intensity`.
+pub struct PhysicalSky {
+    pub intensity: f64,
+    pub ground_albedo: Color,
+    sun: Vec3, // unit, toward the sun
+    theta_sun: f64,
+    zenith: [f64; 3], // Y, x, y straight up
+    coefficients: [Perez; 3],
+    sun_cos: f64, // of the angular radius
+    sun_radiance: Color,
+    irradiance: Color, // on the ground, before intensity
+    sky: DirectionDistribution,
+}
+
+impl PhysicalSky {
+    // sun elevation above the horizon and azimuth from -z toward +x, in degrees
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        Self::new_with_sun(elevation, azimuth, turbidity, 0.53)
+    }
+
+    pub fn new_with_sun(
+        elevation: f64,
+        azimuth: f64,
+        turbidity: f64,
+        angular_diameter: f64,
+    ) -> Self {
+        assert!(
+            (1.7..=10.0).contains(&turbidity),
+            "turbidity out of the model's range"
+        );
+        let t = turbidity;
+        let elevation = degrees_to_radians(clamp(elevation, 0.0, 90.0));
+        let azimuth = degrees_to_radians(azimuth);
+        let sun = Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+        let theta_sun = PI / 2.0 - elevation;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0], theta_sun)
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394], theta_sun)
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886], theta_sun);
+        let zenith_yc = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0], theta_sun)
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516], theta_sun)
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688], theta_sun);
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // Rayleigh and aerosol extinction along the sun's path through the air, at
+        // wavelengths (in um) standing in for red, green and blue
+        let air_mass =
+            1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+        let beta = 0.046_083_658_220_5 * t - 0.045_860_259_285_22;
+        let mut transmittance = Color::zero();
+        for (c, lambda) in [0.65_f64, 0.55, 0.45].iter().enumerate() {
+            let rayleigh = 0.008_735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            transmittance[c] = (-(rayleigh + aerosol) * air_mass).exp();
+        }
+        let sun_cos = degrees_to_radians(angular_diameter / 2.0).cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - sun_cos);
+
+        let mut sky = Self {
+            intensity: 0.05,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            sun,
+            theta_sun,
+            zenith: [zenith_y, zenith_x, zenith_yc],
+            coefficients,
+            sun_cos,
+            sun_radiance: transmittance * (SUN_ILLUMINANCE / sun_solid_angle),
+            irradiance: Color::zero(),
+            sky: DirectionDistribution::new(1, 1, |_, _| 1.0),
+        };
+
+        // light reaching the ground, for its radiance below the horizon
+        let (width, height) = (64, 32);
+        let mut irradiance = sky.sun_radiance * (sun_solid_angle * sun.y);
+        for row in height / 2..height {
+            let v = (row as f64 + 0.5) / height as f64;
+            let d_omega = 2.0 * PI * PI * (PI * (v - 0.5)).cos() / (width * height) as f64;
+            for column in 0..width {
+                let dir = uv_to_direction((column as f64 + 0.5) / width as f64, v);
+                irradiance += sky.sky_radiance(&dir) * (dir.y * d_omega);
+            }
+        }
+        sky.irradiance = irradiance;
+        sky.sky = DirectionDistribution::new(width, height, |u, v| {
+            luminance(&sky.diffuse(&uv_to_direction(u, v)))
+        });
+        sky
+    }
+
+    // Perez's distribution scaled to the zenith value, converted from xyY
+    fn sky_radiance(&self, dir: &Vec3) -> Color {
+        let cos_theta = dir.y.max(1e-4);
+        let gamma = clamp(*dir * self.sun, -1.0, 1.0).acos();
+        let mut yxy = [0.0; 3];
+        for (c, value) in yxy.iter_mut().enumerate() {
+            *value = self.zenith[c] * perez(&self.coefficients[c], cos_theta, gamma)
+                / perez(&self.coefficients[c], 1.0, self.theta_sun);
+        }
+        let (luma, x, y) = (yxy[0], yxy[1], yxy[2]);
+        xyz_to_linear_srgb(&Vec3::new(x / y * luma, luma, (1.0 - x - y) / y * luma))
+    }
+
+    // everything but the sun
+    fn diffuse(&self, dir: &Vec3) -> Color {
+        if dir.y < 0.0 {
+            Vec3::elemul(self.ground_albedo, self.irradiance) / PI
+        } else {
+            self.sky_radiance(dir)
+        }
+    }
+
+    fn in_sun(&self, dir: &Vec3) -> bool {
+        *dir * self.sun >= self.sun_cos
+    }
+}
+
+impl Background for PhysicalSky {
+    fn value(&self, dir: &Vec3) -> Color {
+        let mut radiance = self.diffuse(dir);
+        if dir.y >= 0.0 && self.in_sun(dir) {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn sample(&self) -> Option<(Vec3, f64)> {
+        let dir = if random_double(0.0, 1.0) < SUN_PROBABILITY {
+            ONB::build_from_w(&self.sun).local(&random_in_cone(self.sun_cos))
+        } else {
+            self.sky.sample()?.0
+        };
+        Some((dir, self.pdf(&dir)))
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f64 {
+        let sun = if self.in_sun(dir) {
+            1.0 / (2.0 * PI * (1.0 - self.sun_cos))
+        } else {
+            0.0
+        };
+        SUN_PROBABILITY * sun + (1.0 - SUN_PROBABILITY) * self.sky.pdf(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zenith_luminance() {
+        // Preetham's zenith luminance for turbidity 3 and the sun 45 degrees up
+        let sky = PhysicalSky::new(45.0, 30.0, 3.0);
+        let chi = (4.0 / 9.0 - 3.0 / 120.0) * (PI / 2.0);
+        let expected = (4.0453 * 3.0 - 4.9710) * chi.tan() - 0.2155 * 3.0 + 2.4192;
+        let zenith = luminance(&sky.value(&Vec3::new(0.0, 1.0, 0.0))) / sky.intensity;
+        assert!((zenith - expected).abs() < 1e-3 * expected, "{}", zenith);
+        // the sun outshines the sky, and is redder near the horizon
+        assert!(sky.value(&sky.sun).y > 1000.0 * sky.value(&Vec3::new(0.0, 1.0, 0.0)).y);
+        let low = PhysicalSky::new(2.0, 30.0, 3.0);
+        assert!(low.sun_radiance.x / low.sun_radiance.z > sky.sun_radiance.x / sky.sun_radiance.z);
+    }
+
+    #[test]
+    fn test_sample_pdf() {
+        let sky = PhysicalSky::new(20.0, -60.0, 4.0);
+        let mut in_sun = 0;
+        for _ in 0..1000 {
+            let (dir, pdf) = sky.sample().unwrap();
+            assert!((sky.pdf(&dir) - pdf).abs() < 1e-6 * pdf);
+            if sky.in_sun(&dir) {
+                in_sun += 1;
+            }
+        }
+        assert!(in_sun > 400 && in_sun < 600);
+    }
+}
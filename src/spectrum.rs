@@ -0,0 +1,179 @@
+use crate::rtweekend::*;
+use crate::vec3::*;
+
+// Spectral rendering: every path carries one wavelength, colors are turned into spectral
+// values on the way and the result is projected back to sRGB at the film.
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+// integral of the y matching function over [LAMBDA_MIN, LAMBDA_MAX]
+const CIE_Y_INTEGRAL: f64 = 106.92;
+// scales XYZ so that a flat spectrum comes out as D65 white, i.e. (1, 1, 1) in sRGB
+const WHITE_BALANCE: [f64; 3] = [0.95047 / 0.99855, 1.0, 1.08883 / 0.99912];
+
+pub fn sample_wavelength() -> f64 {
+    random_double(LAMBDA_MIN, LAMBDA_MAX)
+}
+
+// Smits 1999, "An RGB to Spectrum Conversion for Reflectances": ten bins from 380 to 720 nm
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// value at `wavelength` of a smooth spectrum with the given RGB color, used for albedos,
+// emission and everything else the materials return
+pub fn rgb_to_spectrum(color: &Color, wavelength: f64) -> f64 {
+    let bin = ((wavelength - 380.0) / 34.0) as usize;
+    let bin = bin.min(9);
+    let (r, g, b) = (color.x, color.y, color.z);
+    if r <= g && r <= b {
+        let mut s = r * SMITS_WHITE[bin];
+        if g <= b {
+            s += (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin];
+        } else {
+            s += (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin];
+        }
+        s
+    } else if g <= r && g <= b {
+        let mut s = g * SMITS_WHITE[bin];
+        if r <= b {
+            s += (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin];
+        } else {
+            s += (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin];
+        }
+        s
+    } else {
+        let mut s = b * SMITS_WHITE[bin];
+        if r <= g {
+            s += (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin];
+        } else {
+            s += (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin];
+        }
+        s
+    }
+}
+
+fn piecewise_gaussian(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 colour matching functions, multi-lobe fit of Wyman, Sloan and Shirley 2013
+pub fn cie_xyz(wavelength: f64) -> Vec3 {
+    let l = wavelength;
+    Vec3::new(
+        1.056 * piecewise_gaussian(l, 599.8, 37.9, 31.0)
+            + 0.362 * piecewise_gaussian(l, 442.0, 16.0, 26.7)
+            - 0.065 * piecewise_gaussian(l, 501.1, 20.4, 26.2),
+        0.821 * piecewise_gaussian(l, 568.8, 46.9, 40.5)
+            + 0.286 * piecewise_gaussian(l, 530.9, 16.3, 31.1),
+        1.217 * piecewise_gaussian(l, 437.0, 11.8, 36.0)
+            + 0.681 * piecewise_gaussian(l, 459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Color {
+    Color::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// Monte Carlo estimate in linear sRGB of radiance `value` carried at a uniformly sampled
+// wavelength. Can be negative for saturated colors outside of sRGB.
+pub fn spectral_to_rgb(value: f64, wavelength: f64) -> Color {
+    let xyz = cie_xyz(wavelength) * (value * (LAMBDA_MAX - LAMBDA_MIN) / CIE_Y_INTEGRAL);
+    xyz_to_linear_srgb(&Vec3::new(
+        xyz.x * WHITE_BALANCE[0],
+        xyz.y * WHITE_BALANCE[1],
+        xyz.z * WHITE_BALANCE[2],
+    ))
+}
+
+// Wavelength dependent IOR of glass, wavelengths in nm
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 }, // n = a + b / lambda^2, lambda in um
+    Sellmeier { b: [f64; 3], c: [f64; 3] }, // c in um^2
+}
+
+impl Dispersion {
+    // borosilicate crown glass
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    // dense flint glass, disperses about three times as much as BK7
+    pub fn sf10() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.62153902, 0.256287842, 1.64447552],
+            c: [0.0122241457, 0.0595736775, 147.468793],
+        }
+    }
+
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+// Fraunhofer d line, the wavelength IORs are usually quoted at
+pub const LAMBDA_D: f64 = 587.6;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_round_trip() {
+        // a white albedo under a flat spectrum averages to white
+        let n = 4000;
+        let mut sum = Color::zero();
+        for i in 0..n {
+            let wavelength = LAMBDA_MIN + (i as f64 + 0.5) * (LAMBDA_MAX - LAMBDA_MIN) / n as f64;
+            let value = rgb_to_spectrum(&Color::ones(), wavelength);
+            sum += spectral_to_rgb(value, wavelength) / n as f64;
+        }
+        for c in 0..3 {
+            assert!((sum[c] - 1.0).abs() < 0.02, "{:?}", sum);
+        }
+    }
+
+    #[test]
+    fn test_bk7() {
+        assert!((Dispersion::bk7().ior(LAMBDA_D) - 1.5168).abs() < 1e-3);
+        assert!(Dispersion::bk7().ior(450.0) > Dispersion::bk7().ior(650.0));
+    }
+}
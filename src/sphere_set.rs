@@ -0,0 +1,279 @@
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+const MAX_LEAF_SIZE: usize = 4;
+
+// Node of a flattened BVH. Leaves own `count` spheres starting at `offset` in `order`,
+// inner nodes have their first child right after them and the second at `offset`.
+struct FlatNode {
+    bbox: AABB,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+// Many spheres sharing one material, e.g. particles from a simulation. Centers, radii and
+// optional per-particle colors live in flat arrays with their own BVH, so there is no
+// Arc<Sphere> per particle.
+pub struct SphereSet {
+    centers: Vec<Point>,
+    radii: Vec<f64>,
+    colors: Vec<Color>, // empty when all particles use the plain material
+    mat_ptr: Arc<dyn Material>,
+    order: Vec<usize>,
+    nodes: Vec<FlatNode>,
+}
+
+impl SphereSet {
+    pub fn new(centers: Vec<Point>, radii: Vec<f64>, mat_ptr: Arc<dyn Material>) -> Self {
+        Self::new_with_colors(centers, radii, vec![], mat_ptr)
+    }
+
+    // colors tint whatever the material returns, so a white Lambertian gives plain colors
+    pub fn new_with_colors(
+        centers: Vec<Point>,
+        radii: Vec<f64>,
+        colors: Vec<Color>,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        assert_eq!(centers.len(), radii.len());
+        assert!(colors.is_empty() || colors.len() == centers.len());
+        let mut tmp = Self {
+            order: (0..centers.len()).collect(),
+            centers,
+            radii,
+            colors,
+            mat_ptr,
+            nodes: Vec::new(),
+        };
+        if !tmp.centers.is_empty() {
+            let len = tmp.order.len();
+            tmp.build(0, len);
+        }
+        tmp
+    }
+
+    fn sphere_box(&self, i: usize) -> AABB {
+        let r = Vec3::new(self.radii[i], self.radii[i], self.radii[i]);
+        AABB::new(&(self.centers[i] - r), &(self.centers[i] + r))
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let mut bbox = self.sphere_box(self.order[start]);
+        let mut centroid_min = self.centers[self.order[start]];
+        let mut centroid_max = centroid_min;
+        for k in start + 1..end {
+            let i = self.order[k];
+            bbox = surrounding_box(&bbox, &self.sphere_box(i));
+            for a in 0..3 {
+                centroid_min[a] = centroid_min[a].min(self.centers[i][a]);
+                centroid_max[a] = centroid_max[a].max(self.centers[i][a]);
+            }
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(FlatNode {
+            bbox,
+            offset: start,
+            count: end - start,
+            axis: 0,
+        });
+        if end - start <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let centers = &self.centers;
+        self.order[start..end].sort_by(|a, b| {
+            centers[*a][axis]
+                .partial_cmp(&centers[*b][axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mid = start + (end - start) / 2;
+        self.build(start, mid);
+        let second = self.build(mid, end);
+        self.nodes[index] = FlatNode {
+            bbox: self.nodes[index].bbox.clone(),
+            offset: second,
+            count: 0,
+            axis,
+        };
+        index
+    }
+
+    fn hit_sphere(&self, i: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let oc = r.orig - self.centers[i];
+        let a = r.dire.squared_length();
+        let half_b = oc * r.dire;
+        let c = oc.squared_length() - self.radii[i] * self.radii[i];
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let temp = (-half_b - root) / a;
+        if temp < t_max && temp > t_min {
+            return Some(temp);
+        }
+        let temp = (-half_b + root) / a;
+        if temp < t_max && temp > t_min {
+            return Some(temp);
+        }
+        None
+    }
+}
+
+impl Hittable for SphereSet {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let mut closest_so_far = t_max;
+        let mut hit_index = None;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !node.bbox.hit(r, t_min, closest_so_far) {
+                continue;
+            }
+            if node.count > 0 {
+                for k in node.offset..node.offset + node.count {
+                    let i = self.order[k];
+                    if let Some(t) = self.hit_sphere(i, r, t_min, closest_so_far) {
+                        closest_so_far = t;
+                        hit_index = Some(i);
+                    }
+                }
+            } else if r.dire[node.axis] < 0.0 {
+                // visit the nearer child first
+                stack.push(n + 1);
+                stack.push(node.offset);
+            } else {
+                stack.push(node.offset);
+                stack.push(n + 1);
+            }
+        }
+
+        match hit_index {
+            Some(i) => {
+                rec.t = closest_so_far;
+                rec.p = r.at(rec.t);
+                let outward_normal = (rec.p - self.centers[i]) / self.radii[i];
+                rec.set_face_normal(r, &outward_normal);
+                get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+                get_sphere_tangents(&outward_normal, self.radii[i], &mut rec.dpdu, &mut rec.dpdv);
+                rec.set_material(self.mat_ptr.clone());
+                if !self.colors.is_empty() {
+                    rec.tint = self.colors[i];
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        *output_box = self.nodes[0].bbox.clone();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::{random_double, INFINITY};
+
+    fn ray(orig: Point, dire: Vec3) -> Ray {
+        Ray {
+            orig,
+            dire,
+            tm: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_hit_matches_brute_force() {
+        let n = 300;
+        let centers: Vec<Point> = (0..n)
+            .map(|_| {
+                Point::new(
+                    random_double(-10.0, 10.0),
+                    random_double(-10.0, 10.0),
+                    random_double(-10.0, 10.0),
+                )
+            })
+            .collect();
+        let radii: Vec<f64> = (0..n).map(|_| random_double(0.1, 1.0)).collect();
+        let set = SphereSet::new(centers, radii, Arc::new(Lambertian::new(Color::ones())));
+        for _ in 0..1000 {
+            let r = ray(
+                Point::new(random_double(-12.0, 12.0), random_double(-12.0, 12.0), 15.0),
+                Vec3::new(random_double(-0.5, 0.5), random_double(-0.5, 0.5), -1.0),
+            );
+            let closest = (0..n)
+                .filter_map(|i| set.hit_sphere(i, &r, 0.001, INFINITY))
+                .fold(INFINITY, f64::min);
+            let mut rec = HitRecord::new(set.mat_ptr.clone());
+            if set.hit(&r, 0.001, INFINITY, &mut rec) {
+                assert!((rec.t - closest).abs() < 1e-12);
+            } else {
+                assert!(closest.is_infinite());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tint_and_tangents() {
+        let red = Color::new(1.0, 0.0, 0.0);
+        let blue = Color::new(0.0, 0.0, 1.0);
+        let set = SphereSet::new_with_colors(
+            vec![Point::new(-2.0, 0.0, 0.0), Point::new(2.0, 0.0, 0.0)],
+            vec![1.0, 0.5],
+            vec![red, blue],
+            Arc::new(Lambertian::new(Color::ones())),
+        );
+        let mut rec = HitRecord::new(set.mat_ptr.clone());
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        assert!(set.hit(
+            &ray(Point::new(-2.0, 0.0, 5.0), down),
+            0.001,
+            INFINITY,
+            &mut rec
+        ));
+        assert_eq!(rec.tint, red);
+        assert!(set.hit(
+            &ray(Point::new(2.0, 0.3, 5.0), down),
+            0.001,
+            INFINITY,
+            &mut rec
+        ));
+        assert_eq!(rec.tint, blue);
+
+        // the same tangents a lone Sphere gives
+        let mut expected = HitRecord::new(set.mat_ptr.clone());
+        let sphere = Sphere::new(Point::new(2.0, 0.0, 0.0), 0.5, set.mat_ptr.clone());
+        sphere.hit(
+            &ray(Point::new(2.0, 0.3, 5.0), down),
+            0.001,
+            INFINITY,
+            &mut expected,
+        );
+        assert!((rec.dpdu - expected.dpdu).length() < 1e-12);
+        assert!((rec.dpdv - expected.dpdv).length() < 1e-12);
+        assert!(rec.dpdu.length() > 0.0);
+    }
+}
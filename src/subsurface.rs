@@ -0,0 +1,95 @@
+use crate::hittable::HitRecord;
+use crate::material::*;
+use crate::medium::*;
+use crate::phase::*;
+use crate::ray::Ray;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// single scattering albedo that makes a semi-infinite medium look like `albedo` (Chiang et
+// al. 2016, "Practical and Controllable Subsurface Scattering for Production Path Tracing")
+pub fn single_scattering_albedo(albedo: f64) -> f64 {
+    let a = clamp(albedo, 0.0, 0.999);
+    let t = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1.0 - t * t
+}
+
+// Random walk subsurface scattering (skin, marble, wax): light refracts through a glass-like
+// boundary and scatters around in a homogeneous medium until it leaves the object. `albedo`
+// is the color the object ends up with, `mfp` the mean free path per channel, in scene units.
+// The object has to be closed, and paths need enough depth when mfp is small against it.
+pub struct Subsurface {
+    pub albedo: Arc<dyn Texture>,
+    pub mfp: Color,
+    pub phase_function: Arc<dyn PhaseFunction>,
+    boundary: Dielectric,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Arc<dyn Texture>, mfp: Color, ior: f64) -> Self {
+        Self {
+            albedo,
+            mfp,
+            phase_function: Arc::new(Isotropic::new()),
+            boundary: Dielectric::new(ior),
+        }
+    }
+
+    pub fn new_from_color(albedo: Color, mfp: Color, ior: f64) -> Self {
+        Self::new(Arc::new(SolidColor::new(albedo)), mfp, ior)
+    }
+
+    // the medium under the point where the path enters
+    fn medium(&self, rec: &HitRecord) -> HomogeneousMedium {
+        let albedo = self.albedo.value_at(rec);
+        let mut sigma_s = Color::zero();
+        let mut sigma_a = Color::zero();
+        for c in 0..3 {
+            let sigma_t = 1.0 / self.mfp[c].max(1e-6);
+            let single = single_scattering_albedo(albedo[c]);
+            sigma_s[c] = single * sigma_t;
+            sigma_a[c] = (1.0 - single) * sigma_t;
+        }
+        HomogeneousMedium::new_with_phase(sigma_a, sigma_s, self.phase_function.clone())
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.boundary.scatter(r_in, rec, attenuation, scattered)
+    }
+
+    fn interface(&self, rec: &HitRecord) -> Option<MediumInterface> {
+        let mut interface = self.boundary.interface(rec)?;
+        if rec.front_face {
+            interface.interior = Some(Arc::new(self.medium(rec)));
+        }
+        Some(interface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_scattering_albedo() {
+        assert!(single_scattering_albedo(0.0).abs() < 1e-3);
+        assert!((single_scattering_albedo(0.999) - 1.0).abs() < 1e-3);
+        // multiple scattering brightens, so the single scattering albedo is higher
+        let mut last = 0.0;
+        for i in 1..10 {
+            let a = single_scattering_albedo(i as f64 / 10.0);
+            assert!(a > i as f64 / 10.0 && a > last && a < 1.0);
+            last = a;
+        }
+    }
+}
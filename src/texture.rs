@@ -1,12 +1,28 @@
 //use crate::rtweekend::*;
+use crate::exr::load_exr;
+use crate::hittable::HitRecord;
 use crate::perlin::*;
 use crate::rtweekend::*;
 use crate::vec3::*;
-use image::GenericImageView;
+use image::hdr::HdrDecoder;
+use image::{DynamicImage, GenericImageView};
+use std::fs::File;
+use std::io::BufReader;
 use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point) -> Color;
+
+    // value at a hit, which can use the whole record: image textures filter over the pixel
+    // footprint in its (u, v) derivatives, triplanar projection needs the normal
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, &rec.p)
+    }
+
+    // opacity in [0, 1], read by AlphaMask
+    fn alpha(&self, _u: f64, _v: f64, _p: &Point) -> f64 {
+        1.0
+    }
 }
 
 pub struct SolidColor {
@@ -25,34 +41,62 @@ impl Texture for SolidColor {
     }
 }
 
+// constant texture for scalar parameters, which read the first channel
+pub fn scalar(x: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(Color::new(x, x, x)))
+}
+
+// 3D checker in space, flipping every pi / frequency along each axis
 pub struct CheckerTexture {
-    odd: Arc<dyn Texture>,
-    even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub even: Arc<dyn Texture>,
+    pub frequency: f64,
 }
 
 impl CheckerTexture {
     pub fn new(c1: Color, c2: Color) -> Self {
+        Self::new_with_frequency(c1, c2, 10.0)
+    }
+
+    pub fn new_with_frequency(c1: Color, c2: Color, frequency: f64) -> Self {
         Self {
             even: Arc::new(SolidColor::new(c1)),
             odd: Arc::new(SolidColor::new(c2)),
+            frequency,
         }
     }
+
+    fn is_odd(&self, p: &Point) -> bool {
+        let f = self.frequency;
+        let sines = (p.x * f).sin() * (f * p.y).sin() * (f * p.z).sin();
+        sines < 0.0
+    }
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: &Point) -> Color {
-        let sines = (p.x * 10.0).sin() * (10.0 * p.y).sin() * (10.0 * p.z).sin();
-        if sines < 0.0 {
+        if self.is_odd(p) {
             self.odd.value(u, v, p)
         } else {
             self.even.value(u, v, p)
         }
     }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.is_odd(&rec.p) {
+            self.odd.value_at(rec)
+        } else {
+            self.even.value_at(rec)
+        }
+    }
 }
 
+// Perlin noise mapped to [0, 1]. With a speed it moves through the 4th dimension over the
+// ray time, so it changes during the shutter interval (or across frames).
 pub struct NoiseTexture {
     pub noise: Perlin,
     pub scale: f64,
+    pub speed: f64,
 }
 
 impl NoiseTexture {
@@ -60,55 +104,353 @@ impl NoiseTexture {
         Self {
             noise: Perlin::new(),
             scale: sc,
+            speed: 0.0,
         }
     }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
-        Color::ones()
-            * 0.5
-            * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(&(*p * self.scale), 7)).sin())
+        Color::ones() * 0.5 * (1.0 + self.noise.noise(&(*p * self.scale)))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        if self.speed == 0.0 {
+            return self.value(rec.u, rec.v, &rec.p);
+        }
+        let n = self
+            .noise
+            .noise4(&(rec.p * self.scale), rec.time * self.speed);
+        Color::ones() * 0.5 * (1.0 + n)
+    }
+}
+
+// what lookups outside of [0, 1] read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.max(0).min(n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    Trilinear, // bilinear in the two mip levels closest to the pixel footprint
+}
+
+struct MipLevel {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>, // rgba in [0, 1], rows from the top
+}
+
+impl MipLevel {
+    // half the size, each texel the average of the 2x2 block above it
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    let t = self.texels[sy * self.width + sx];
+                    for c in 0..4 {
+                        sum[c] += t[c] / 4.0;
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+// How the values of 8 and 16 bit images are encoded: colors are usually sRGB, data such as
+// roughness, normal and bump maps linear. Float images (HDR, EXR) are always linear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
     }
 }
 
+// Image lookups, with bilinear or mip-mapped trilinear filtering. The footprint comes from
+// the ray differentials of camera rays, later bounces read the full resolution image.
 pub struct ImageTexture {
     pub width: u32,
     pub height: u32,
-    img: image::DynamicImage,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+    levels: Vec<MipLevel>,
 }
 
 impl ImageTexture {
     pub fn new(filename: &str) -> Self {
-        let img = image::open(filename).unwrap();
+        Self::new_with_color_space(filename, ColorSpace::Srgb)
+    }
+
+    pub fn new_with_color_space(filename: &str, color_space: ColorSpace) -> Self {
+        let extension = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+        match extension.as_str() {
+            "exr" => {
+                let exr = load_exr(filename);
+                Self::new_from_texels(exr.width, exr.height, exr.texels)
+            }
+            "hdr" => {
+                let file = File::open(filename).expect("failed to open the HDR");
+                let decoder = HdrDecoder::new(BufReader::new(file)).unwrap();
+                let meta = decoder.metadata();
+                let texels = decoder
+                    .read_image_hdr()
+                    .unwrap()
+                    .iter()
+                    .map(|p| [p[0], p[1], p[2], 1.0])
+                    .collect();
+                Self::new_from_texels(meta.width as usize, meta.height as usize, texels)
+            }
+            _ => Self::new_from_image(image::open(filename).unwrap(), color_space),
+        }
+    }
+
+    // for images made in code, e.g. masks; 16 bit images keep their precision
+    pub fn new_from_image(img: DynamicImage, color_space: ColorSpace) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut texels = vec![[0.0, 0.0, 0.0, 1.0]; width * height];
+        if let Some(samples) = img.as_flat_samples_u16() {
+            // gray, gray and alpha, rgb or rgba
+            let n = samples.layout.channels;
+            for y in 0..height {
+                for x in 0..width {
+                    let s = |c: u8| {
+                        *samples.get_sample(c, x as u32, y as u32).unwrap() as f32 / 65535.0
+                    };
+                    let t = &mut texels[y * width + x];
+                    for (c, x) in t.iter_mut().take(3).enumerate() {
+                        *x = s(if n >= 3 { c as u8 } else { 0 });
+                    }
+                    if n % 2 == 0 {
+                        t[3] = s(n - 1);
+                    }
+                }
+            }
+        } else {
+            for (x, y, p) in img.pixels() {
+                let t = &mut texels[y as usize * width + x as usize];
+                for c in 0..4 {
+                    t[c] = p[c] as f32 / 255.0;
+                }
+            }
+        }
+        if color_space == ColorSpace::Srgb {
+            for t in texels.iter_mut() {
+                for x in t.iter_mut().take(3) {
+                    *x = srgb_to_linear(*x);
+                }
+            }
+        }
+        Self::new_from_texels(width, height, texels)
+    }
+
+    // linear rgba, rows from the top
+    pub fn new_from_texels(width: usize, height: usize, texels: Vec<[f32; 4]>) -> Self {
+        assert_eq!(texels.len(), width * height);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while levels.last().unwrap().width > 1 || levels.last().unwrap().height > 1 {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
         Self {
-            width: img.width(),
-            height: img.height(),
-            img,
+            width: width as u32,
+            height: height as u32,
+            wrap: WrapMode::Clamp,
+            filter: FilterMode::Trilinear,
+            levels,
+        }
+    }
+
+    pub fn new_with_wrap(filename: &str, wrap: WrapMode) -> Self {
+        let mut texture = Self::new(filename);
+        texture.wrap = wrap;
+        texture
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> [f64; 4] {
+        let l = &self.levels[level];
+        let t = l.texels[self.wrap.apply(y, l.height) * l.width + self.wrap.apply(x, l.width)];
+        [t[0] as f64, t[1] as f64, t[2] as f64, t[3] as f64]
+    }
+
+    #[allow(clippy::many_single_char_names)]
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> [f64; 4] {
+        let l = &self.levels[level];
+        let x = u * l.width as f64 - 0.5;
+        let y = (1.0 - v) * l.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mut result = [0.0; 4];
+        for (dx, dy, w) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ]
+        .iter()
+        {
+            let t = self.texel(level, x0 + dx, y0 + dy);
+            for c in 0..4 {
+                result[c] += w * t[c];
+            }
+        }
+        result
+    }
+
+    // rgba at (u, v) for a footprint of `width` texels of the full resolution image
+    #[allow(clippy::many_single_char_names)]
+    fn lookup(&self, u: f64, v: f64, width: f64) -> [f64; 4] {
+        match self.filter {
+            FilterMode::Nearest => {
+                let l = &self.levels[0];
+                let x = (u * l.width as f64).floor() as i64;
+                let y = ((1.0 - v) * l.height as f64).floor() as i64;
+                self.texel(0, x, y)
+            }
+            FilterMode::Bilinear => self.bilinear(0, u, v),
+            FilterMode::Trilinear => {
+                let last = (self.levels.len() - 1) as f64;
+                let level = clamp(width.max(1e-8).log2(), 0.0, last);
+                let lo = level.floor();
+                let a = self.bilinear(lo as usize, u, v);
+                if level - lo < 1e-9 {
+                    return a;
+                }
+                let b = self.bilinear(lo as usize + 1, u, v);
+                let f = level - lo;
+                [
+                    a[0] + f * (b[0] - a[0]),
+                    a[1] + f * (b[1] - a[1]),
+                    a[2] + f * (b[2] - a[2]),
+                    a[3] + f * (b[3] - a[3]),
+                ]
+            }
         }
     }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
-        let u = clamp(u, 0.0, 1.0);
-        let v = 1.0 - clamp(v, 0.0, 1.0);
+        let t = self.lookup(u, v, 0.0);
+        Color::new(t[0], t[1], t[2])
+    }
 
-        let mut i = (u * self.width as f64) as u32;
-        let mut j = (v * self.height as f64) as u32;
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        // longer of the two axes of the footprint, in texels
+        let (width, height) = (self.width as f64, self.height as f64);
+        let along_x = (rec.dudx * width).hypot(rec.dvdx * height);
+        let along_y = (rec.dudy * width).hypot(rec.dvdy * height);
+        let t = self.lookup(rec.u, rec.v, along_x.max(along_y));
+        Color::new(t[0], t[1], t[2])
+    }
 
-        if i >= self.width {
-            i = self.width - 1;
-        }
-        if j >= self.height {
-            j = self.height - 1;
-        }
-        let color_scale = 1.0 / 255.0;
-        let pixel_color = self.img.get_pixel(i, j);
-        Color::new(
-            color_scale * pixel_color[0] as f64,
-            color_scale * pixel_color[1] as f64,
-            color_scale * pixel_color[2] as f64,
-        )
+    // images without an alpha channel read as opaque
+    fn alpha(&self, u: f64, v: f64, _p: &Point) -> f64 {
+        self.lookup(u, v, 0.0)[3]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XYRect;
+    use crate::hittable::Hittable;
+    use crate::material::Lambertian;
+    use crate::ray::{Ray, RayDifferential};
+    use image::RgbImage;
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(9, 4), 1);
+        assert_eq!(WrapMode::Clamp.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Clamp.apply(9, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(5, 4), 2);
+        assert_eq!(WrapMode::Mirror.apply(9, 4), 1);
+    }
+
+    #[test]
+    fn test_footprint_picks_mip_level() {
+        // a 4x4 checkerboard of black and white texels, seen from far enough away that a
+        // pixel covers the whole image, averages to grey
+        let img = RgbImage::from_fn(4, 4, |x, y| {
+            if (x + y) % 2 == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let texture = ImageTexture::new_from_image(DynamicImage::ImageRgb8(img), ColorSpace::Srgb);
+        let mat = Arc::new(Lambertian::new(Color::ones()));
+        let rect = XYRect::new(0.0, 1.0, 0.0, 1.0, 0.0, mat.clone());
+        let ray = |x: f64, y: f64| Ray {
+            orig: Point::new(0.5, 0.5, 5.0),
+            dire: Vec3::new(x, y, -5.0),
+            tm: 0.0,
+        };
+
+        let mut rec = HitRecord::new(mat);
+        let r = ray(0.0, 0.0);
+        assert!(rect.hit(&r, 0.001, INFINITY, &mut rec));
+        rec.compute_differentials(&RayDifferential {
+            rx: ray(1.0, 0.0),
+            ry: ray(0.0, 1.0),
+        });
+        assert!((rec.dudx - 1.0).abs() < 1e-9 && (rec.dvdy - 1.0).abs() < 1e-9);
+        assert!(rec.dvdx.abs() < 1e-9 && rec.dudy.abs() < 1e-9);
+        let c = texture.value_at(&rec);
+        assert!((c.x - 0.5).abs() < 1e-6, "{:?}", c);
+
+        // without a footprint, the texel under the point
+        let c = texture.value(0.1, 0.9, &rec.p);
+        assert!((c.x - 1.0).abs() < 1e-6, "{:?}", c);
     }
 }
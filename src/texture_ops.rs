@@ -0,0 +1,311 @@
+use crate::hittable::HitRecord;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// Textures built from other textures, so that looks can be put together in scene code.
+// They pass value_at on to their inputs, which keeps image filtering working through them.
+
+// lerp from `a` to `b` by the first channel of `factor`
+pub struct MixTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub factor: Arc<dyn Texture>,
+}
+
+impl MixTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: Arc<dyn Texture>) -> Self {
+        Self { a, b, factor }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        let t = self.factor.value(u, v, p).x;
+        self.a.value(u, v, p) * (1.0 - t) + self.b.value(u, v, p) * t
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let t = self.factor.value_at(rec).x;
+        self.a.value_at(rec) * (1.0 - t) + self.b.value_at(rec) * t
+    }
+}
+
+pub struct MultiplyTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+
+impl MultiplyTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        Vec3::elemul(self.a.value(u, v, p), self.b.value(u, v, p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        Vec3::elemul(self.a.value_at(rec), self.b.value_at(rec))
+    }
+}
+
+pub struct AddTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+
+impl AddTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for AddTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.a.value(u, v, p) + self.b.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.a.value_at(rec) + self.b.value_at(rec)
+    }
+}
+
+// 1 - c in each channel
+pub struct InvertTexture {
+    pub input: Arc<dyn Texture>,
+}
+
+impl InvertTexture {
+    pub fn new(input: Arc<dyn Texture>) -> Self {
+        Self { input }
+    }
+}
+
+impl Texture for InvertTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        Color::ones() - self.input.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        Color::ones() - self.input.value_at(rec)
+    }
+}
+
+// maps [in_min, in_max] linearly onto [out_min, out_max] in each channel, clamped
+pub struct RemapTexture {
+    pub input: Arc<dyn Texture>,
+    pub in_min: f64,
+    pub in_max: f64,
+    pub out_min: f64,
+    pub out_max: f64,
+}
+
+impl RemapTexture {
+    pub fn new(
+        input: Arc<dyn Texture>,
+        in_min: f64,
+        in_max: f64,
+        out_min: f64,
+        out_max: f64,
+    ) -> Self {
+        Self {
+            input,
+            in_min,
+            in_max,
+            out_min,
+            out_max,
+        }
+    }
+
+    fn remap(&self, c: Color) -> Color {
+        let mut out = Color::zero();
+        for i in 0..3 {
+            let t = clamp((c[i] - self.in_min) / (self.in_max - self.in_min), 0.0, 1.0);
+            out[i] = self.out_min + t * (self.out_max - self.out_min);
+        }
+        out
+    }
+}
+
+impl Texture for RemapTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.remap(self.input.value(u, v, p))
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.remap(self.input.value_at(rec))
+    }
+}
+
+// colors the first channel of the input by interpolating between stops, which are
+// (position, color) pairs in increasing position
+pub struct ColorRampTexture {
+    pub input: Arc<dyn Texture>,
+    pub stops: Vec<(f64, Color)>,
+}
+
+impl ColorRampTexture {
+    pub fn new(input: Arc<dyn Texture>, stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs stops");
+        Self { input, stops }
+    }
+
+    fn ramp(&self, x: f64) -> Color {
+        let next = match self.stops.iter().position(|(t, _)| *t > x) {
+            Some(0) => return self.stops[0].1,
+            Some(i) => i,
+            None => return self.stops[self.stops.len() - 1].1,
+        };
+        let (t0, c0) = self.stops[next - 1];
+        let (t1, c1) = self.stops[next];
+        let f = (x - t0) / (t1 - t0);
+        c0 * (1.0 - f) + c1 * f
+    }
+}
+
+impl Texture for ColorRampTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        self.ramp(self.input.value(u, v, p).x)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.ramp(self.input.value_at(rec).x)
+    }
+}
+
+// looks the input up at (u, v) scaled, then rotated (degrees) around the origin, then
+// offset, e.g. to tile an image with WrapMode::Repeat
+pub struct UvTransformTexture {
+    pub input: Arc<dyn Texture>,
+    pub scale: (f64, f64),
+    pub rotation: f64,
+    pub offset: (f64, f64),
+}
+
+impl UvTransformTexture {
+    pub fn new(
+        input: Arc<dyn Texture>,
+        scale: (f64, f64),
+        rotation: f64,
+        offset: (f64, f64),
+    ) -> Self {
+        Self {
+            input,
+            scale,
+            rotation,
+            offset,
+        }
+    }
+
+    // the linear part, also applied to the derivatives
+    fn linear(&self, u: f64, v: f64) -> (f64, f64) {
+        let (sin, cos) = degrees_to_radians(self.rotation).sin_cos();
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        (cos * u - sin * v, sin * u + cos * v)
+    }
+
+    fn transform(&self, u: f64, v: f64) -> (f64, f64) {
+        let (u, v) = self.linear(u, v);
+        (u + self.offset.0, v + self.offset.1)
+    }
+}
+
+impl Texture for UvTransformTexture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        let (u, v) = self.transform(u, v);
+        self.input.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let mut rec = rec.clone();
+        let (u, v) = self.transform(rec.u, rec.v);
+        let (dudx, dvdx) = self.linear(rec.dudx, rec.dvdx);
+        let (dudy, dvdy) = self.linear(rec.dudy, rec.dvdy);
+        rec.u = u;
+        rec.v = v;
+        rec.dudx = dudx;
+        rec.dvdx = dvdx;
+        rec.dudy = dudy;
+        rec.dvdy = dvdy;
+        self.input.value_at(&rec)
+    }
+}
+
+// Projects the input along the three axes in world space, (u, v) = (y, z), (x, z) or (x, y)
+// times `scale`, and blends by how much the normal faces each axis. Gives surfaces without
+// useful texture coordinates (meshes, boxes) an undistorted image. Lookups without a hit
+// record get the z projection.
+pub struct TriplanarTexture {
+    pub input: Arc<dyn Texture>,
+    pub scale: f64,
+    pub sharpness: f64, // exponent on the blend weights, higher gives harder seams
+}
+
+impl TriplanarTexture {
+    pub fn new(input: Arc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            input,
+            scale,
+            sharpness: 4.0,
+        }
+    }
+}
+
+impl Texture for TriplanarTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        self.input.value(p.x * self.scale, p.y * self.scale, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let p = rec.p * self.scale;
+        let mut w = Vec3::zero();
+        for i in 0..3 {
+            w[i] = rec.normal[i].abs().powf(self.sharpness);
+        }
+        let w = w / (w.x + w.y + w.z);
+        self.input.value(p.y, p.z, &rec.p) * w.x
+            + self.input.value(p.x, p.z, &rec.p) * w.y
+            + self.input.value(p.x, p.y, &rec.p) * w.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the texture coordinates as a color
+    struct Uv {}
+    impl Texture for Uv {
+        fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
+            Color::new(u, v, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_color_ramp() {
+        let ramp = ColorRampTexture::new(
+            Arc::new(Uv {}),
+            vec![
+                (0.2, Color::new(1.0, 0.0, 0.0)),
+                (0.6, Color::new(0.0, 0.0, 1.0)),
+            ],
+        );
+        let p = Point::zero();
+        assert_eq!(ramp.value(0.0, 0.0, &p), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(ramp.value(0.9, 0.0, &p), Color::new(0.0, 0.0, 1.0));
+        let mid = ramp.value(0.4, 0.0, &p);
+        assert!((mid.x - 0.5).abs() < 1e-9 && (mid.z - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_uv_transform() {
+        let t = UvTransformTexture::new(Arc::new(Uv {}), (2.0, 4.0), 90.0, (0.5, 0.0));
+        // (0.25, 0.125) scales to (0.5, 0.5), rotates to (-0.5, 0.5) and moves to (0, 0.5)
+        let c = t.value(0.25, 0.125, &Point::zero());
+        assert!(c.x.abs() < 1e-9 && (c.y - 0.5).abs() < 1e-9, "{:?}", c);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::hittable::HitRecord;
+use crate::rtweekend::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+// wavelengths (nm) averaged into the red, green and blue channels
+const CHANNEL_WAVELENGTHS: [[f64; 3]; 3] = [
+    [610.0, 640.0, 670.0],
+    [520.0, 550.0, 580.0],
+    [430.0, 460.0, 490.0],
+];
+
+#[derive(Clone, Copy, Debug)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let r = self.norm_sqr().sqrt();
+        let re = ((r + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((r - self.re) / 2.0).max(0.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    // e^(i z)
+    fn exp_i(self) -> Self {
+        let scale = (-self.im).exp();
+        Self::new(scale * self.re.cos(), scale * self.re.sin())
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        Self::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, o: Self) -> Self {
+        let d = o.norm_sqr();
+        Self::new(
+            (self.re * o.re + self.im * o.im) / d,
+            (self.im * o.re - self.re * o.im) / d,
+        )
+    }
+}
+
+// Reflectance of a film of IOR n2 and the given thickness (nm) on a substrate eta3 + i k3,
+// lit from a medium of IOR n1, at one wavelength (nm). Sums the multiple reflections inside
+// the film (Airy), unpolarized.
+pub fn airy_reflectance(
+    cos_theta_i: f64,
+    n1: f64,
+    n2: f64,
+    thickness: f64,
+    eta3: f64,
+    k3: f64,
+    wavelength: f64,
+) -> f64 {
+    let cos1 = Complex::new(clamp(cos_theta_i, 0.0, 1.0), 0.0);
+    let n1 = Complex::new(n1, 0.0);
+    let n2 = Complex::new(n2, 0.0);
+    let n3 = Complex::new(eta3, k3);
+    let one = Complex::new(1.0, 0.0);
+    let sin2 = one - cos1 * cos1;
+    // Snell's law with complex angles where light can't propagate (or is absorbed)
+    let cos_in = |n: Complex| (one - sin2 * (n1 / n) * (n1 / n)).sqrt();
+    let cos2 = cos_in(n2);
+    let cos3 = cos_in(n3);
+
+    let rs = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (na * ca - nb * cb) / (na * ca + nb * cb)
+    };
+    let rp = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+        (nb * ca - na * cb) / (nb * ca + na * cb)
+    };
+    let phase = (n2 * cos2 * Complex::new(4.0 * PI * thickness / wavelength, 0.0)).exp_i();
+    let airy = |r12: Complex, r23: Complex| (r12 + r23 * phase) / (one + r12 * r23 * phase);
+
+    let s = airy(rs(n1, cos1, n2, cos2), rs(n2, cos2, n3, cos3));
+    let p = airy(rp(n1, cos1, n2, cos2), rp(n2, cos2, n3, cos3));
+    clamp(0.5 * (s.norm_sqr() + p.norm_sqr()), 0.0, 1.0)
+}
+
+// complex IOR of a conductor with the given normal incidence reflectance (Gulbrandsen 2014,
+// edge tint equal to the reflectance), for coating Metal which only has an albedo
+pub fn conductor_from_reflectance(r: &Color) -> (Color, Color) {
+    let mut eta = Color::zero();
+    let mut k = Color::zero();
+    for c in 0..3 {
+        let r = clamp(r[c], 0.0, 0.99);
+        let g = r;
+        let n = g * (1.0 - r) / (1.0 + r) + (1.0 - g) * (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+        eta[c] = n;
+        k[c] = ((r * (n + 1.0) * (n + 1.0) - (n - 1.0) * (n - 1.0)) / (1.0 - r))
+            .max(0.0)
+            .sqrt();
+    }
+    (eta, k)
+}
+
+// Thin transparent coating (soap, oil, oxide layers) that makes reflections iridescent.
+// The thickness is in micrometres and read from the first channel, so NoiseTexture gives
+// 0 to 1 um.
+pub struct ThinFilm {
+    pub thickness: Arc<dyn Texture>,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        Self::new_from_texture(scalar(thickness), ior)
+    }
+
+    pub fn new_from_texture(thickness: Arc<dyn Texture>, ior: f64) -> Self {
+        Self { thickness, ior }
+    }
+
+    // per channel reflectance at the hit, coming from a medium of IOR outer_ior onto a
+    // substrate eta + i k
+    pub fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_theta: f64,
+        outer_ior: f64,
+        eta: &Color,
+        k: &Color,
+    ) -> Color {
+        let thickness = 1000.0 * self.thickness.value_at(rec).x.max(0.0);
+        let mut r = Color::zero();
+        for (c, wavelengths) in CHANNEL_WAVELENGTHS.iter().enumerate() {
+            for wavelength in wavelengths.iter() {
+                r[c] += airy_reflectance(
+                    cos_theta,
+                    outer_ior,
+                    self.ior,
+                    thickness,
+                    eta[c],
+                    k[c],
+                    *wavelength,
+                ) / wavelengths.len() as f64;
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::fr_dielectric;
+
+    #[test]
+    fn test_vanishing_film() {
+        // without thickness, or with the IOR of the outside, only the substrate remains
+        for cos in [1.0, 0.7, 0.2].iter() {
+            let bare = fr_dielectric(*cos, 1.0, 1.5);
+            assert!((airy_reflectance(*cos, 1.0, 1.33, 0.0, 1.5, 0.0, 550.0) - bare).abs() < 1e-9);
+            assert!((airy_reflectance(*cos, 1.0, 1.0, 300.0, 1.5, 0.0, 550.0) - bare).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_quarter_wave_coating() {
+        // a quarter wave of MgF2 on glass cancels most of the reflection at normal incidence
+        let n2 = 1.38;
+        let r = airy_reflectance(1.0, 1.0, n2, 550.0 / (4.0 * n2), 1.52, 0.0, 550.0);
+        let expected = ((1.52 - n2 * n2) / (1.52 + n2 * n2)).powi(2);
+        assert!((r - expected).abs() < 1e-9);
+        assert!(r < fr_dielectric(1.0, 1.0, 1.52) / 2.0);
+    }
+}
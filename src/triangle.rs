@@ -8,6 +8,7 @@ pub struct Triangle {
     pub point1: Point,
     pub point2: Point,
     pub point0: Point,
+    pub normals: Option<[Vec3; 3]>, // vertex normals of point1, point2, point0
     pub mp: Arc<dyn Material>,
 }
 
@@ -17,6 +18,23 @@ impl Triangle {
             point1,
             point2,
             point0,
+            normals: None,
+            mp,
+        }
+    }
+
+    pub fn new_with_normals(
+        point1: Point,
+        point2: Point,
+        point0: Point,
+        normals: [Vec3; 3],
+        mp: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            point1,
+            point2,
+            point0,
+            normals: Some(normals),
             mp,
         }
     }
@@ -60,11 +78,22 @@ impl Hittable for Triangle {
 
         let outward_normal = Vec3::cross(edge1, edge2).unit();
         rec.set_face_normal(r, &outward_normal);
+        if let Some(n) = &self.normals {
+            // smooth shading, kept on the same side as the geometric normal
+            let shading_normal = (n[2] * (1.0 - u - v) + n[0] * u + n[1] * v).unit();
+            rec.normal = if rec.front_face {
+                shading_normal
+            } else {
+                -shading_normal
+            };
+        }
         rec.u = u;
         rec.v = v;
+        rec.dpdu = edge1;
+        rec.dpdv = edge2;
         rec.t = t;
         rec.p = r.at(t);
-        rec.mat_ptr = self.mp.clone();
+        rec.set_material(self.mp.clone());
         true
     }
     fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
@@ -75,9 +104,10 @@ impl Hittable for Triangle {
         let max_y = self.point0.y.max(self.point1.y).max(self.point2.y);
         let max_z = self.point0.z.max(self.point1.z).max(self.point2.z);
 
+        // padded so that axis aligned triangles (e.g. from meshes) don't get a flat box
         *output_box = AABB::new(
-            &Point::new(min_x, min_y, min_z),
-            &Point::new(max_x, max_y, max_z),
+            &Point::new(min_x - 0.0001, min_y - 0.0001, min_z - 0.0001),
+            &Point::new(max_x + 0.0001, max_y + 0.0001, max_z + 0.0001),
         );
         true
     }
@@ -54,6 +54,14 @@ pub fn random_unit_vector() -> Vec3 {
     Vec3::new(r * a.cos(), r * a.sin(), z)
 }
 
+// uniform over the directions within acos(cos_max) of +z
+pub fn random_in_cone(cos_max: f64) -> Vec3 {
+    let z = 1.0 - random_double(0.0, 1.0) * (1.0 - cos_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let a = random_double(0.0, 2.0 * PI);
+    Vec3::new(r * a.cos(), r * a.sin(), z)
+}
+
 /*
 pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
     let in_unit_sphere = random_in_unit_sphere();